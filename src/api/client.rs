@@ -1,28 +1,170 @@
 //! API client for making requests to Bilibili API
 
-use reqwest::{Client, RequestBuilder};
+use std::sync::RwLock;
+
+use log::warn;
+use reqwest::{Client, Proxy, RequestBuilder, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::identity::Identity,
+    limit::{LimitConfig, Limiter},
+};
+
+/// 所有请求共用的 User-Agent，与现行桌面版 Chrome 保持一致
+pub const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/141.0.0.0 Safari/537.36";
+
+/// 接口基础地址，可指向本地模拟服务器用于离线测试
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EndpointConfig {
+    /// 主站接口地址
+    pub api: String,
+    /// 登录接口地址
+    pub passport: String,
+    /// 音频流下载地址的替换主机，如 `https://upos-sz-mirrorali.bilivideo.com`
+    pub cdn: Option<String>,
+}
+
+impl Default for EndpointConfig {
+    fn default() -> Self {
+        Self {
+            api: "https://api.bilibili.com".to_string(),
+            passport: "https://passport.bilibili.com".to_string(),
+            cdn: None,
+        }
+    }
+}
+
+impl EndpointConfig {
+    pub fn api_url(&self, path: &str) -> String {
+        format!("{}{}", self.api.trim_end_matches('/'), path)
+    }
+
+    pub fn passport_url(&self, path: &str) -> String {
+        format!("{}{}", self.passport.trim_end_matches('/'), path)
+    }
+
+    /// 将音频流地址的协议与主机替换为配置的 CDN 地址，保留路径与查询参数
+    pub fn rewrite_cdn(&self, url: &str) -> String {
+        match (&self.cdn, Url::parse(url)) {
+            (Some(cdn), Ok(parsed)) => {
+                let query = parsed
+                    .query()
+                    .map(|q| format!("?{}", q))
+                    .unwrap_or_default();
+                format!("{}{}{}", cdn.trim_end_matches('/'), parsed.path(), query)
+            }
+            _ => url.to_string(),
+        }
+    }
+}
 
 pub struct ApiClient {
     pub client: Client,
     pub cookies: Vec<String>,
+    pub endpoints: EndpointConfig,
+    identity: RwLock<Identity>,
+    limiter: Limiter,
 }
 
 impl ApiClient {
-    pub fn new(cookies: Vec<String>) -> Self {
+    /// 创建客户端，并生成一份临时的设备标识（buvid3 / b_nut）
+    ///
+    /// `proxy` 为全部请求使用的代理，来自 `[defaults] proxy` 或 `--proxy`
+    pub fn new(cookies: Vec<String>, endpoints: EndpointConfig, proxy: Option<&str>) -> Self {
         Self {
-            client: Client::new(),
+            client: build_client(proxy),
             cookies,
+            endpoints,
+            identity: RwLock::new(Identity::generate()),
+            limiter: Limiter::default(),
+        }
+    }
+
+    pub fn limiter(&self) -> &Limiter {
+        &self.limiter
+    }
+
+    pub fn set_limits(&mut self, limits: &LimitConfig) {
+        self.limiter = Limiter::new(limits);
+    }
+
+    pub fn identity(&self) -> Identity {
+        self.identity.read().unwrap().clone()
+    }
+
+    pub fn set_identity(&self, identity: Identity) {
+        *self.identity.write().unwrap() = identity;
+    }
+
+    /// 从登录 Cookie 中取出指定名称的值，例如 `bili_jct`
+    pub fn cookie_value(&self, name: &str) -> Option<&str> {
+        let prefix = format!("{}=", name);
+        self.cookies.iter().find_map(|cookie| {
+            cookie
+                .strip_prefix(&prefix)
+                .map(|rest| rest.split(';').next().unwrap_or_default())
+        })
+    }
+
+    /// 合并登录 Cookie 与设备标识 Cookie，登录 Cookie 中已有的同名项优先
+    fn cookie_header(&self) -> String {
+        let mut cookies = self.cookies.clone();
+        for cookie in self.identity.read().unwrap().cookies() {
+            let name = cookie.split('=').next().unwrap_or_default();
+            if self.cookie_value(name).is_none() {
+                cookies.push(cookie);
+            }
         }
+        cookies.join("; ")
     }
 
     pub fn get(&self, url: &str) -> RequestBuilder {
-        self.client
-            .get(url)
-            .header("Cookie", self.cookies.join("; "))
-            .header(
-                "User-Agent",
-                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/58.0.3029.110 Safari/537.36",
-            )
+        self.with_headers(self.client.get(url))
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.with_headers(self.client.post(url))
+    }
+
+    fn with_headers(&self, req: RequestBuilder) -> RequestBuilder {
+        req.header("Cookie", self.cookie_header())
+            .header("User-Agent", USER_AGENT)
             .header("Referer", "https://www.bilibili.com/")
     }
 }
+
+/// 按配置的代理创建 HTTP 客户端，代理无效时不使用代理
+fn build_client(proxy: Option<&str>) -> Client {
+    let Some(proxy) = proxy else {
+        return Client::new();
+    };
+    match Proxy::all(proxy).and_then(|proxy| Client::builder().proxy(proxy).build()) {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("代理 {} 无效，不使用代理: {}", proxy, e);
+            Client::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_cdn() {
+        let url = "https://xy1x2x3x4xy.mcdn.bilivideo.cn:4483/upgcxcode/30280.m4s?e=abc&os=mcdn";
+        assert_eq!(EndpointConfig::default().rewrite_cdn(url), url);
+
+        let endpoints = EndpointConfig {
+            cdn: Some("http://127.0.0.1:8080/".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            endpoints.rewrite_cdn(url),
+            "http://127.0.0.1:8080/upgcxcode/30280.m4s?e=abc&os=mcdn"
+        );
+    }
+}
@@ -1,14 +1,19 @@
 //! API endpoint definitions
 
+use log::{info, warn};
 use serde::Deserialize;
 
 use crate::{
+    api::identity,
     error::Result,
     models::{ApiResponse, VideoBasicInfo},
     user::User,
     wbi::WbiSendExt,
 };
 
+/// WBI 签名校验失败时接口返回的错误码
+const WBI_SIGN_ERROR_CODES: [i64; 2] = [-352, -403];
+
 #[derive(Debug, Deserialize)]
 pub struct GenData {
     pub url: String,
@@ -43,15 +48,82 @@ pub struct PollResp {
     pub data: PollData,
 }
 
-pub async fn get_video_info(user: &User, bvid: &str) -> Result<VideoBasicInfo> {
-    let url = "https://api.bilibili.com/x/web-interface/view";
-    let params = [("bvid", bvid.to_string())];
-    let req = user.get(url).query(&params);
-    let wbi_keys = user.get_wbi_keys().await;
-    let resp = req
-        .wbi_send(user.get_client(), wbi_keys.0, wbi_keys.1)
+/// 发送带 WBI 签名的 GET 请求，签名校验失败时刷新密钥并重试一次
+async fn wbi_get(user: &User, url: &str, params: &[(&str, String)]) -> Result<serde_json::Value> {
+    user.renew_ticket().await;
+    let mut refreshed = false;
+    loop {
+        let (img_key, sub_key) = user.get_wbi_keys().await?;
+        let permit = user.limiter().api(url).await;
+        let resp = wbi_request(user, url, params)
+            .wbi_send(user.get_client(), &img_key, &sub_key)
+            .await?;
+        let json: serde_json::Value = resp.json().await?;
+        // 刷新密钥的请求同样需要许可，必须先释放，否则并发数为 1 时会死锁
+        drop(permit);
+        let code = json["code"].as_i64().unwrap_or_default();
+        if !refreshed && WBI_SIGN_ERROR_CODES.contains(&code) {
+            warn!("WBI签名校验失败 (code={})，刷新密钥后重试", code);
+            user.refresh_wbi_keys().await?;
+            refreshed = true;
+            continue;
+        }
+        return Ok(json);
+    }
+}
+
+/// 待签名的请求，空间类接口在签名前加上 dm_img_* 参数
+fn wbi_request(user: &User, url: &str, params: &[(&str, String)]) -> reqwest::RequestBuilder {
+    let request = user.get(url).query(params);
+    match url.contains("/x/space/") {
+        true => request.query(&identity::dm_img_params()),
+        false => request,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpiData {
+    pub b_3: String,
+    pub b_4: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TicketData {
+    pub ticket: String,
+    pub created_at: i64,
+    pub ttl: i64,
+}
+
+/// 申请 buvid3 / buvid4 设备标识
+pub async fn get_buvid(user: &User) -> Result<SpiData> {
+    let url = user.api_url("/x/frontend/finger/spi");
+    let api_resp: ApiResponse<SpiData> = user.send(user.get(&url)).await?.json().await?;
+    if api_resp.code != 0 {
+        return Err(crate::error::BilidownError::ApiError(format!(
+            "API错误: {}",
+            api_resp.message
+        )));
+    }
+    api_resp
+        .data
+        .ok_or_else(|| crate::error::BilidownError::ApiError("API返回数据为空".to_string()))
+}
+
+/// 通过 GenWebTicket 申请 bili_ticket，`csrf` 为登录 Cookie 中的 bili_jct，游客为空
+pub async fn gen_web_ticket(user: &User, csrf: &str) -> Result<TicketData> {
+    let url = user.api_url("/bapis/bilibili.api.ticket.v1.Ticket/GenWebTicket");
+    let ts = identity::now();
+    let params = [
+        ("key_id", "ec02".to_string()),
+        ("hexsign", identity::ticket_hexsign(ts)),
+        ("context[ts]", ts.to_string()),
+        ("csrf", csrf.to_string()),
+    ];
+    let api_resp: ApiResponse<TicketData> = user
+        .send(user.post(&url).query(&params))
+        .await?
+        .json()
         .await?;
-    let api_resp: ApiResponse<VideoBasicInfo> = resp.json().await?;
     if api_resp.code != 0 {
         return Err(crate::error::BilidownError::ApiError(format!(
             "API错误: {}",
@@ -63,12 +135,55 @@ pub async fn get_video_info(user: &User, bvid: &str) -> Result<VideoBasicInfo> {
         .ok_or_else(|| crate::error::BilidownError::ApiError("API返回数据为空".to_string()))
 }
 
+pub async fn get_video_info(user: &User, bvid: &str) -> Result<VideoBasicInfo> {
+    let api_resp = view(user, bvid).await?;
+    if api_resp.code != 0 {
+        return Err(crate::error::BilidownError::ApiError(format!(
+            "API错误: {}",
+            api_resp.message
+        )));
+    }
+    api_resp
+        .data
+        .ok_or_else(|| crate::error::BilidownError::ApiError("API返回数据为空".to_string()))
+}
+
+/// 稿件已删除时 view 接口返回的错误码
+///
+/// 62002（稿件不可见）可能只是审核中或暂时隐藏，按普通错误处理，不触发失效分P的处理策略
+const UNAVAILABLE_CODES: [i32; 2] = [-404, 62004];
+
+/// 获取视频信息，稿件已删除时返回 `None`，其他错误与 [`get_video_info`] 相同
+pub async fn find_video_info(user: &User, bvid: &str) -> Result<Option<VideoBasicInfo>> {
+    let api_resp = view(user, bvid).await?;
+    if UNAVAILABLE_CODES.contains(&api_resp.code) {
+        info!("视频 {} 已失效: {}", bvid, api_resp.message);
+        return Ok(None);
+    }
+    if api_resp.code != 0 {
+        return Err(crate::error::BilidownError::ApiError(format!(
+            "API错误: {}",
+            api_resp.message
+        )));
+    }
+    api_resp
+        .data
+        .map(Some)
+        .ok_or_else(|| crate::error::BilidownError::ApiError("API返回数据为空".to_string()))
+}
+
+async fn view(user: &User, bvid: &str) -> Result<ApiResponse<VideoBasicInfo>> {
+    let url = user.api_url("/x/web-interface/view");
+    let params = [("bvid", bvid.to_string())];
+    Ok(serde_json::from_value(wbi_get(user, &url, &params).await?)?)
+}
+
 pub async fn get_play_url_dash(
     user: &User,
     bvid: &str,
     cid: i64,
 ) -> Result<crate::download::PlayUrlDashResp> {
-    let url = "https://api.bilibili.com/x/player/playurl";
+    let url = user.api_url("/x/player/playurl");
     let params = vec![
         ("bvid", bvid.to_string()),
         ("cid", cid.to_string()),
@@ -76,13 +191,8 @@ pub async fn get_play_url_dash(
         ("fnver", "0".to_string()),
         ("otype", "json".to_string()),
     ];
-    let wbi_keys = user.get_wbi_keys().await;
-    let resp = user
-        .get(url)
-        .query(&params)
-        .wbi_send(user.get_client(), wbi_keys.0, wbi_keys.1)
-        .await?;
-    let dash_resp: crate::download::PlayUrlDashResp = resp.json().await?;
+    let dash_resp: crate::download::PlayUrlDashResp =
+        serde_json::from_value(wbi_get(user, &url, &params).await?)?;
     if dash_resp.code != 0 {
         return Err(crate::error::BilidownError::ApiError(format!(
             "API错误: {}",
@@ -92,16 +202,38 @@ pub async fn get_play_url_dash(
     Ok(dash_resp)
 }
 
+/// 获取分P的 CC 字幕列表，包括 AI 生成的字幕
+pub async fn get_subtitles(
+    user: &User,
+    bvid: &str,
+    cid: i64,
+) -> Result<Vec<crate::lyrics::SubtitleTrack>> {
+    let url = user.api_url("/x/player/wbi/v2");
+    let params = [("bvid", bvid.to_string()), ("cid", cid.to_string())];
+    let api_resp: ApiResponse<crate::lyrics::PlayerInfo> =
+        serde_json::from_value(wbi_get(user, &url, &params).await?)?;
+    if api_resp.code != 0 {
+        return Err(crate::error::BilidownError::ApiError(format!(
+            "API错误: {}",
+            api_resp.message
+        )));
+    }
+    Ok(api_resp
+        .data
+        .map(|data| data.subtitle.subtitles)
+        .unwrap_or_default())
+}
+
 pub async fn generate_qr_login(user: &User) -> Result<GenResp> {
-    let url = "https://passport.bilibili.com/x/passport-login/web/qrcode/generate";
-    let res: GenResp = user.get(url).send().await?.json().await?;
+    let url = user.passport_url("/x/passport-login/web/qrcode/generate");
+    let res: GenResp = user.send(user.get(&url)).await?.json().await?;
     Ok(res)
 }
 
 pub async fn poll_qr_login(user: &User, key: &str) -> Result<(PollResp, Vec<String>)> {
-    let url = "https://passport.bilibili.com/x/passport-login/web/qrcode/poll";
-    let req = user.get(url).query(&[("qrcode_key", key)]);
-    let resp = req.send().await?;
+    let url = user.passport_url("/x/passport-login/web/qrcode/poll");
+    let req = user.get(&url).query(&[("qrcode_key", key)]);
+    let resp = user.send(req).await?;
     // capture set-cookie headers before consuming the body
     let cookies: Vec<String> = resp
         .headers()
@@ -113,10 +245,29 @@ pub async fn poll_qr_login(user: &User, key: &str) -> Result<(PollResp, Vec<Stri
     Ok((pr, cookies))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct NavData {
+    #[serde(rename = "isLogin")]
+    pub is_login: bool,
+    pub mid: Option<i64>,
+    pub uname: Option<String>,
+    #[serde(rename = "vipStatus")]
+    pub vip_status: Option<i32>,
+}
+
+/// 获取当前账号信息，未登录时接口返回 -101 但仍带有 data
+pub async fn get_nav_info(user: &User) -> Result<NavData> {
+    let url = user.api_url("/x/web-interface/nav");
+    let api_resp: ApiResponse<NavData> = user.send(user.get(&url)).await?.json().await?;
+    api_resp
+        .data
+        .ok_or_else(|| crate::error::BilidownError::ApiError(api_resp.message))
+}
+
 pub async fn verify_login(user: &User) -> Result<bool> {
-    let url = "https://api.bilibili.com/x/web-interface/nav";
-    let req = user.get(url);
-    let resp = req.send().await?;
+    let url = user.api_url("/x/web-interface/nav");
+    let req = user.get(&url);
+    let resp = user.send(req).await?;
     if !resp.status().is_success() {
         return Ok(false);
     }
@@ -127,3 +278,38 @@ pub async fn verify_login(user: &User) -> Result<bool> {
         Ok(false)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{api::client::EndpointConfig, wbi};
+
+    #[test]
+    fn test_space_requests_sign_dm_img_params() {
+        let user = User::new_anonymous(EndpointConfig::default(), None);
+        let (img_key, sub_key) = (
+            "7cd084941338484aae1ad9425b84077c",
+            "4932caff0ff746eab6f01bf08b70ac45",
+        );
+        let signed_query = |url: &str| {
+            let params = [("mid", "2".to_string())];
+            let mut request = wbi_request(&user, url, &params).build().unwrap();
+            wbi::sign(&mut request, img_key, sub_key);
+            request
+                .url()
+                .query_pairs()
+                .into_owned()
+                .collect::<HashMap<_, _>>()
+        };
+
+        let query = signed_query("https://api.bilibili.com/x/space/wbi/arc/search");
+        for key in ["dm_img_list", "dm_img_str", "dm_cover_img_str", "w_rid"] {
+            assert!(query.contains_key(key), "缺少 {}", key);
+        }
+        assert_eq!(query["dm_img_list"], "[]");
+        let query = signed_query("https://api.bilibili.com/x/web-interface/view");
+        assert!(query.contains_key("w_rid") && !query.contains_key("dm_img_str"));
+    }
+}
@@ -0,0 +1,166 @@
+//! Request identity (buvid / bili_ticket) used to pass Bilibili risk control
+
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::error::Result;
+
+/// GenWebTicket 接口签名所用的 HMAC 密钥
+const TICKET_HMAC_KEY: &[u8] = b"XgwSnGZ1p";
+
+/// bili_ticket 剩余有效期不足该秒数时提前续期
+const TICKET_RENEW_MARGIN: i64 = 60 * 60;
+
+/// `WebGL 1.0 (OpenGL ES 2.0 Chromium)` 的 base64 去掉末尾两个字符
+const DM_IMG_STR: &str = "V2ViR0wgMS4wIChPcGVuR0wgRVMgMi4wIENocm9taXVtKQ";
+
+/// 常见 Intel 核显 WebGL 渲染器字符串的 base64 去掉末尾两个字符
+const DM_COVER_IMG_STR: &str = "QU5HTEUgKEludGVsLCBJbnRlbChSKSBVSEQgR3JhcGhpY3MgNjMwICgweDAwMDAzRTlCKSBEaXJlY3QzRDExIHZzXzVfMCBwc181XzAsIEQzRDExKUdvb2dsZSBJbmMuIChJbnRlbC";
+
+/// 设备标识与风控票据，持久化后在多次运行间保持一致
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identity {
+    pub buvid3: String,
+    pub buvid4: Option<String>,
+    pub b_nut: u64,
+    pub bili_ticket: Option<String>,
+    pub bili_ticket_expires: Option<i64>,
+}
+
+impl Identity {
+    /// 在本地生成新的设备标识，buvid4 与 bili_ticket 需要向接口申请
+    pub fn generate() -> Self {
+        Self {
+            buvid3: generate_buvid3(),
+            buvid4: None,
+            b_nut: now(),
+            bili_ticket: None,
+            bili_ticket_expires: None,
+        }
+    }
+
+    /// 从文件读取身份信息，文件不存在或格式错误时返回 `None`
+    pub async fn load(path: &Path) -> Option<Self> {
+        let contents = tokio::fs::read_to_string(path).await.ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, serde_json::to_string_pretty(self)?).await?;
+        Ok(())
+    }
+
+    /// bili_ticket 是否缺失或即将过期
+    pub fn ticket_expired(&self) -> bool {
+        match (&self.bili_ticket, self.bili_ticket_expires) {
+            (Some(_), Some(expires)) => expires - (now() as i64) < TICKET_RENEW_MARGIN,
+            _ => true,
+        }
+    }
+
+    /// 以 `name=value` 形式返回需要附加到请求上的 Cookie
+    pub fn cookies(&self) -> Vec<String> {
+        let mut cookies = vec![
+            format!("buvid3={}", self.buvid3),
+            format!("b_nut={}", self.b_nut),
+        ];
+        if let Some(buvid4) = &self.buvid4 {
+            cookies.push(format!("buvid4={}", buvid4));
+        }
+        if let (Some(ticket), Some(expires)) = (&self.bili_ticket, self.bili_ticket_expires) {
+            cookies.push(format!("bili_ticket={}", ticket));
+            cookies.push(format!("bili_ticket_expires={}", expires));
+        }
+        cookies
+    }
+}
+
+/// buvid3 的格式为 `XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX` + 5 位数字 + `infoc`
+fn generate_buvid3() -> String {
+    let mut rng = rand::rng();
+    let hex: String = (0..32)
+        .map(|_| format!("{:X}", rng.random_range(0..16u8)))
+        .collect();
+    format!(
+        "{}-{}-{}-{}-{}{:05}infoc",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32],
+        rng.random_range(0..100000u32)
+    )
+}
+
+/// 计算 GenWebTicket 请求的 `hexsign` 参数
+pub fn ticket_hexsign(timestamp: u64) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(TICKET_HMAC_KEY).expect("HMAC 接受任意长度的密钥");
+    mac.update(format!("ts{}", timestamp).as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// 空间类 WBI 接口额外要求的 dm_img_* 参数
+pub fn dm_img_params() -> Vec<(&'static str, String)> {
+    vec![
+        ("dm_img_list", "[]".to_string()),
+        ("dm_img_str", DM_IMG_STR.to_string()),
+        ("dm_cover_img_str", DM_COVER_IMG_STR.to_string()),
+        (
+            "dm_img_inter",
+            r#"{"ds":[],"wh":[0,0,0],"of":[0,0,0]}"#.to_string(),
+        ),
+    ]
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticket_hexsign() {
+        assert_eq!(
+            ticket_hexsign(1734000000),
+            "3e549ef86088bcd83a89014626692bd23c2fb0c637a0d740187f9862f7a561a1"
+        );
+    }
+
+    #[test]
+    fn test_generate_buvid3() {
+        let buvid3 = generate_buvid3();
+        assert_eq!(buvid3.len(), 46);
+        assert!(buvid3.ends_with("infoc"));
+        assert_eq!(buvid3.matches('-').count(), 4);
+    }
+
+    #[test]
+    fn test_ticket_expired() {
+        let mut identity = Identity::generate();
+        assert!(identity.ticket_expired());
+        identity.bili_ticket = Some("ticket".to_string());
+        identity.bili_ticket_expires = Some(now() as i64 + 3 * 24 * 60 * 60);
+        assert!(!identity.ticket_expired());
+        identity.bili_ticket_expires = Some(now() as i64 + 60);
+        assert!(identity.ticket_expired());
+    }
+}
@@ -0,0 +1,180 @@
+//! Download archive recording which parts have already been downloaded
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Local};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Result, utils};
+
+/// 已下载的分P记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub bvid: String,
+    pub cid: i64,
+    pub page: i32,
+    pub title: String, // 视频标题
+    pub part: String,  // 分P标题
+    pub path: PathBuf, // 输出文件路径
+    pub downloaded_at: DateTime<Local>,
+    /// 订阅单独设置的输出目录，同一分P可以分别下载到多个目录
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<PathBuf>,
+}
+
+impl ArchiveEntry {
+    fn key(&self) -> String {
+        key(self.cid, self.scope.as_deref())
+    }
+}
+
+/// 未设置输出目录的记录以 cid 为键，与旧版本的记录文件兼容
+fn key(cid: i64, scope: Option<&Path>) -> String {
+    match scope {
+        Some(scope) => format!("{}@{}", cid, scope.display()),
+        None => cid.to_string(),
+    }
+}
+
+/// 以 cid 与输出目录为键的下载记录，每次写入后立即保存到文件
+pub struct Archive {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<String, ArchiveEntry>>,
+    save_lock: tokio::sync::Mutex<()>, // 保证较早的快照不会覆盖较新的
+}
+
+impl Archive {
+    /// 读取下载记录，文件不存在时返回空记录
+    ///
+    /// 文件无法解析时移动到 `<文件名>.corrupt` 并从空记录开始
+    pub fn load(path: &Path) -> Result<Self> {
+        let entries = match std::fs::read_to_string(path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    let moved = utils::move_aside(path)?;
+                    warn!(
+                        "下载记录文件 {:?} 无法解析: {}，已移动到 {:?}，将从空记录开始",
+                        path, e, moved
+                    );
+                    BTreeMap::new()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries: Mutex::new(entries),
+            save_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, cid: i64, scope: Option<&Path>) -> Option<ArchiveEntry> {
+        self.entries.lock().unwrap().get(&key(cid, scope)).cloned()
+    }
+
+    /// 分P已有下载记录且输出文件仍然存在时返回该记录
+    pub fn downloaded(&self, cid: i64, scope: Option<&Path>) -> Option<ArchiveEntry> {
+        self.get(cid, scope).filter(|entry| entry.path.exists())
+    }
+
+    pub fn entries(&self) -> Vec<ArchiveEntry> {
+        self.entries.lock().unwrap().values().cloned().collect()
+    }
+
+    pub async fn insert(&self, entry: ArchiveEntry) -> Result<()> {
+        self.entries.lock().unwrap().insert(entry.key(), entry);
+        self.save().await
+    }
+
+    /// 指定视频在指定输出目录中的全部记录
+    pub fn entries_for(&self, bvid: &str, scope: Option<&Path>) -> Vec<ArchiveEntry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.bvid == bvid && entry.scope.as_deref() == scope)
+            .cloned()
+            .collect()
+    }
+
+    pub async fn remove(&self, cid: i64, scope: Option<&Path>) -> Result<Option<ArchiveEntry>> {
+        let removed = self.entries.lock().unwrap().remove(&key(cid, scope));
+        if removed.is_some() {
+            self.save().await?;
+        }
+        Ok(removed)
+    }
+
+    /// 删除指定视频的全部记录，返回删除的条数
+    pub async fn remove_bvid(&self, bvid: &str) -> Result<usize> {
+        let removed = {
+            let mut entries = self.entries.lock().unwrap();
+            let before = entries.len();
+            entries.retain(|_, entry| entry.bvid != bvid);
+            before - entries.len()
+        };
+        self.save().await?;
+        Ok(removed)
+    }
+
+    pub async fn clear(&self) -> Result<usize> {
+        let removed = std::mem::take(&mut *self.entries.lock().unwrap()).len();
+        self.save().await?;
+        Ok(removed)
+    }
+
+    /// 写入当前全部记录，先写临时文件再替换，中断时不会损坏记录文件
+    async fn save(&self) -> Result<()> {
+        let _guard = self.save_lock.lock().await;
+        let contents = serde_json::to_string_pretty(&*self.entries.lock().unwrap())?;
+        utils::write_atomic(&self.path, contents).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(cid: i64) -> ArchiveEntry {
+        ArchiveEntry {
+            bvid: "BV1fk4y1x7Aa".to_string(),
+            cid,
+            page: 1,
+            title: "测试".to_string(),
+            part: "P1".to_string(),
+            path: PathBuf::from("P1.mp3"),
+            downloaded_at: Local::now(),
+            scope: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_file_is_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive.json");
+        std::fs::write(&path, "{\"1001\": {").unwrap();
+
+        let archive = Archive::load(&path).unwrap();
+        assert!(archive.entries().is_empty());
+        assert!(dir.path().join("archive.json.corrupt").exists());
+
+        archive.insert(entry(1001)).await.unwrap();
+        let names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names.len(), 2, "不应留下临时文件: {:?}", names);
+        assert!(Archive::load(&path).unwrap().get(1001, None).is_some());
+    }
+}
@@ -1,67 +1,391 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use config::ConfigError;
 use dirs;
-use log::debug;
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
 
-use crate::subscription::Subscription;
+use crate::{
+    api::client::EndpointConfig, converter::AudioFormat, daemon::DaemonConfig,
+    download::AudioQuality, limit::LimitConfig, report::OutputFormat, server::ServerConfig,
+    subscription::Subscription,
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
-    /// Bilibili视频ID (如 BV1NfxMedEU6)
-    #[arg(short, long)]
-    pub bvid: Option<String>,
+    /// 要执行的操作，省略时等同于 sync
+    #[command(subcommand)]
+    pub command: Option<Command>,
 
     /// 下载目录
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub output_dir: Option<PathBuf>,
 
     /// Cookie文件路径
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub cookie_file: Option<PathBuf>,
 
     /// 订阅配置文件路径
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub subscription_file: Option<PathBuf>,
 
-    /// 是否只获取视频信息而不下载
-    #[arg(long)]
-    pub info_only: bool,
+    /// 下载记录文件路径
+    #[arg(long, global = true)]
+    pub archive_file: Option<PathBuf>,
+
+    /// 输出音频格式，覆盖配置文件中的 `[defaults] format` 与订阅中的 `format`
+    #[arg(long, value_enum, global = true)]
+    pub audio_format: Option<AudioFormat>,
+
+    /// 音质上限，覆盖配置文件中的 `[defaults] quality` 与订阅中的 `quality`
+    #[arg(long, value_enum, global = true)]
+    pub quality: Option<AudioQuality>,
+
+    /// 同时进行的音频下载数，覆盖配置文件中的 `[defaults] concurrency`
+    #[arg(long, global = true)]
+    pub concurrency: Option<usize>,
+
+    /// 代理地址（如 http://127.0.0.1:7890），覆盖配置文件中的 `[defaults] proxy`
+    #[arg(long, global = true)]
+    pub proxy: Option<String>,
+
+    /// 游客模式，跳过登录，仅下载未登录可获取的音质
+    #[arg(long, global = true)]
+    pub anonymous: bool,
+
+    /// 预演模式：只列出将要下载、跳过与覆盖的分P，不下载音频
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// 结果输出格式，json/ndjson 便于脚本处理
+    #[arg(long, value_enum, default_value_t, global = true)]
+    pub output: OutputFormat,
 
     /// 增加日志详细程度 (-v, -vv, -vvv)
-    #[arg(short, long, action = clap::ArgAction::Count)]
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
     pub verbose: u8,
 
     /// 安静模式，只显示错误
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub quiet: bool,
 }
 
+impl Cli {
+    /// 订阅文件路径，未指定时使用配置目录下的 `ov-bilidown/config.toml`
+    pub fn subscription_file(&self) -> PathBuf {
+        self.subscription_file.clone().unwrap_or_else(|| {
+            let mut path = dirs::config_dir().unwrap_or_else(|| std::env::current_dir().unwrap());
+            path.push("ov-bilidown");
+            path.push("config.toml");
+            path
+        })
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// 扫码登录并保存Cookie
+    Login,
+    /// 删除已保存的Cookie
+    Logout,
+    /// 显示当前登录的账号
+    Whoami,
+    /// 获取视频信息而不下载
+    Info {
+        /// Bilibili视频ID (如 BV1NfxMedEU6)
+        bvid: String,
+    },
+    /// 下载单个视频的音频
+    Download {
+        /// Bilibili视频ID (如 BV1NfxMedEU6)
+        bvid: String,
+
+        #[command(flatten)]
+        templates: TemplateArgs,
+    },
+    /// 处理订阅文件中的全部订阅
+    Sync {
+        #[command(flatten)]
+        select: SelectArgs,
+    },
+    /// 继续上次被中断的下载任务
+    Resume,
+    /// 常驻运行，按间隔定期同步订阅，订阅文件修改后自动重新加载
+    Daemon {
+        /// 同步间隔（秒），覆盖配置文件中的 `[daemon] interval`
+        #[arg(long)]
+        interval: Option<u64>,
+    },
+    /// 启动本地 HTTP/JSON 控制接口，用于提交下载任务与查看进度
+    Serve {
+        /// 监听地址，覆盖配置文件中的 `[server] listen`
+        #[arg(long)]
+        listen: Option<SocketAddr>,
+    },
+    /// 管理订阅文件
+    Subs {
+        #[command(subcommand)]
+        action: SubsCommand,
+    },
+    /// 管理下载记录
+    Archive {
+        #[command(subcommand)]
+        action: ArchiveCommand,
+    },
+}
+
+/// 歌曲元数据模板，支持通配符
+#[derive(Args, Debug, Default)]
+pub struct TemplateArgs {
+    /// 歌曲名模板
+    #[arg(long)]
+    pub title: Option<String>,
+
+    /// 艺术家模板
+    #[arg(long)]
+    pub artist: Option<String>,
+
+    /// 专辑名模板
+    #[arg(long)]
+    pub album: Option<String>,
+}
+
+/// 按名称或标签选择要处理的订阅
+#[derive(Args, Debug, Default)]
+pub struct SelectArgs {
+    /// 只处理名称、标签或 BV 号匹配的订阅，可重复指定
+    #[arg(long, value_name = "NAME|TAG")]
+    pub only: Vec<String>,
+
+    /// 跳过名称、标签或 BV 号匹配的订阅，可重复指定，优先于 --only
+    #[arg(long, value_name = "NAME|TAG")]
+    pub skip: Vec<String>,
+}
+
+impl SelectArgs {
+    pub fn selects(&self, subscription: &Subscription) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|only| subscription.matches(only)))
+            && !self.skip.iter().any(|skip| subscription.matches(skip))
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SubsCommand {
+    /// 列出全部订阅
+    List,
+    /// 获取视频信息确认视频有效后添加订阅
+    Add {
+        /// Bilibili视频ID或链接 (如 BV1NfxMedEU6)
+        video: String,
+
+        #[command(flatten)]
+        templates: TemplateArgs,
+
+        /// 守护模式下的同步间隔（秒）
+        #[arg(long)]
+        interval: Option<u64>,
+    },
+    /// 删除指定视频的订阅
+    Remove {
+        /// Bilibili视频ID或链接 (如 BV1NfxMedEU6)
+        video: String,
+    },
+    /// 修改已有订阅的模板与同步间隔，未指定的字段保持不变
+    Set {
+        /// Bilibili视频ID或链接 (如 BV1NfxMedEU6)
+        video: String,
+
+        #[command(flatten)]
+        templates: TemplateArgs,
+
+        /// 守护模式下的同步间隔（秒）
+        #[arg(long)]
+        interval: Option<u64>,
+
+        /// 删除字段，恢复默认行为，可重复指定
+        #[arg(long, value_name = "FIELD", value_parser = ["title", "artist", "album", "interval"])]
+        unset: Vec<String>,
+    },
+    /// 检查订阅文件，报告错误的字段、未知配置项、无效或重复的 BV 号与未知通配符
+    Check,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ArchiveCommand {
+    /// 列出下载记录
+    List {
+        /// 只显示指定视频的记录
+        bvid: Option<String>,
+    },
+    /// 删除指定视频的下载记录，下次同步时将重新下载
+    Remove {
+        /// Bilibili视频ID (如 BV1NfxMedEU6)
+        bvid: String,
+    },
+    /// 清空下载记录
+    Clear,
+}
+
+/// 全局默认设置，对应配置文件中的 `[defaults]`
+///
+/// 优先级从高到低：命令行参数、环境变量 `OV_BILIDOWN_DEFAULTS__<字段>`、配置文件、内置默认值。
+/// 订阅中单独设置的模板、格式、音质与文件名优先于这里的默认值，命令行中的格式与音质除外，见 [`Overrides`]。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Defaults {
+    /// 下载目录
+    pub output_dir: Option<PathBuf>,
+    /// 下载记录文件路径
+    pub archive_file: Option<PathBuf>,
+    /// 输出格式 `mp3` 或 `flac`
+    pub format: Option<AudioFormat>,
+    /// 音质上限
+    pub quality: Option<AudioQuality>,
+    /// MP3 码率（kbps）
+    pub bitrate: Option<u32>,
+    /// 输出文件名模板（不含扩展名）
+    pub filename: Option<String>,
+    /// 歌曲名模板
+    pub title: Option<String>,
+    /// 艺术家模板
+    pub artist: Option<String>,
+    /// 专辑名模板
+    pub album: Option<String>,
+    /// 同时进行的音频下载数，覆盖 `[limits] download_concurrency`
+    pub concurrency: Option<usize>,
+    /// 全部请求使用的代理地址
+    pub proxy: Option<String>,
+}
+
+impl Defaults {
+    /// 为订阅中未设置的模板、格式、音质、码率与文件名填入默认值
+    pub fn apply(&self, subscription: &mut Subscription) {
+        let fill = |value: &mut Option<String>, default: &Option<String>| {
+            if value.is_none() {
+                value.clone_from(default);
+            }
+        };
+        fill(&mut subscription.title, &self.title);
+        fill(&mut subscription.artist, &self.artist);
+        fill(&mut subscription.album, &self.album);
+        fill(&mut subscription.filename, &self.filename);
+        if subscription.format.is_none() {
+            subscription.format.clone_from(&self.format);
+        }
+        subscription.quality = subscription.quality.or(self.quality);
+        subscription.bitrate = subscription.bitrate.or(self.bitrate);
+    }
+}
+
+/// 命令行中指定的格式与音质，对本次运行的全部订阅生效，优先于订阅中的单独设置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Overrides {
+    pub format: Option<AudioFormat>,
+    pub quality: Option<AudioQuality>,
+}
+
+impl Overrides {
+    pub fn apply(&self, subscription: &mut Subscription) {
+        if self.format.is_some() {
+            subscription.format.clone_from(&self.format);
+        }
+        subscription.quality = self.quality.or(subscription.quality);
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
-    pub bvid: Option<String>,
     pub output_dir: PathBuf,
     pub cookie_file: PathBuf,
-    pub info_only: bool,
+    pub subscription_file: PathBuf,
+    pub archive_file: PathBuf,
+    pub wbi_cache_file: PathBuf,
+    pub identity_file: PathBuf,
+    pub daemon_state_file: PathBuf,
+    pub queue_file: PathBuf,
+    pub anonymous: bool,
+    pub endpoints: EndpointConfig,
+    pub limits: LimitConfig,
+    pub daemon: DaemonConfig,
+    pub server: ServerConfig,
+    pub defaults: Defaults,
+    pub overrides: Overrides,
     pub subscriptions: Vec<Subscription>,
+    /// 订阅文件通过 `include` 包含的其他订阅文件
+    pub included_files: Vec<PathBuf>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct Subscriptions {
+pub(crate) struct Subscriptions {
+    #[serde(default)]
+    #[serde(rename = "sub")]
+    subscriptions: Vec<Subscription>,
+    #[serde(default)]
+    endpoints: EndpointConfig,
+    #[serde(default)]
+    limits: LimitConfig,
+    #[serde(default)]
+    daemon: DaemonConfig,
+    #[serde(default)]
+    server: ServerConfig,
+    #[serde(default)]
+    defaults: Defaults,
+    /// 包含的其他订阅文件，相对路径相对于当前文件所在目录
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    /// 实际加载的被包含文件
+    #[serde(skip)]
+    included_files: Vec<PathBuf>,
+}
+
+/// 被包含的订阅文件只读取其中的订阅与嵌套的 `include`
+#[derive(Debug, Deserialize)]
+pub(crate) struct IncludedSubscriptions {
     #[serde(default)]
     #[serde(rename = "sub")]
     subscriptions: Vec<Subscription>,
+    #[serde(default)]
+    include: Vec<PathBuf>,
 }
 
 impl AppConfig {
-    pub fn new(cli: Cli) -> std::result::Result<Self, ConfigError> {
-        let output_dir = cli.output_dir.unwrap_or_else(|| {
+    pub fn new(cli: &Cli) -> std::result::Result<Self, ConfigError> {
+        let subscription_file = cli.subscription_file();
+        let mut subscriptions = load_subscriptions(&subscription_file)?;
+
+        // 命令行参数优先于环境变量与配置文件
+        let mut defaults = subscriptions.defaults;
+        defaults.output_dir = cli.output_dir.clone().or(defaults.output_dir);
+        defaults.archive_file = cli.archive_file.clone().or(defaults.archive_file);
+        defaults.format = cli.audio_format.clone().or(defaults.format);
+        defaults.quality = cli.quality.or(defaults.quality);
+        defaults.concurrency = cli.concurrency.or(defaults.concurrency);
+        defaults.proxy = cli.proxy.clone().or(defaults.proxy);
+        if let Some(proxy) = &defaults.proxy {
+            reqwest::Proxy::all(proxy)
+                .map_err(|e| ConfigError::Message(format!("代理地址 {} 无效: {}", proxy, e)))?;
+        }
+        if let Some(concurrency) = defaults.concurrency {
+            subscriptions.limits.download_concurrency = concurrency;
+        }
+        let overrides = Overrides {
+            format: cli.audio_format.clone(),
+            quality: cli.quality,
+        };
+        for subscription in &mut subscriptions.subscriptions {
+            defaults.apply(subscription);
+            overrides.apply(subscription);
+        }
+
+        let output_dir = defaults.output_dir.clone().unwrap_or_else(|| {
             dirs::download_dir().unwrap_or_else(|| std::env::current_dir().unwrap())
         });
 
-        let cookie_file = cli.cookie_file.unwrap_or_else(|| {
+        let cookie_file = cli.cookie_file.clone().unwrap_or_else(|| {
             let mut config_dir =
                 dirs::config_dir().unwrap_or_else(|| std::env::current_dir().unwrap());
             config_dir.push("ov-bilidown");
@@ -69,29 +393,289 @@ impl AppConfig {
             config_dir
         });
 
-        let subscription_path = cli.subscription_file.unwrap_or_else(|| {
+        let wbi_cache_file = {
+            let mut path = dirs::cache_dir().unwrap_or_else(|| std::env::current_dir().unwrap());
+            path.push("ov-bilidown");
+            path.push("wbi_keys.json");
+            path
+        };
+
+        let identity_file = {
             let mut path = dirs::config_dir().unwrap_or_else(|| std::env::current_dir().unwrap());
             path.push("ov-bilidown");
-            path.push("config.toml");
+            path.push("identity.json");
+            path
+        };
+
+        let archive_file = defaults.archive_file.clone().unwrap_or_else(|| {
+            let mut path = dirs::data_dir().unwrap_or_else(|| std::env::current_dir().unwrap());
+            path.push("ov-bilidown");
+            path.push("archive.json");
             path
         });
-        let subscription_config = config::Config::builder()
-            .add_source(config::File::from(subscription_path).required(false))
-            .build()?;
 
-        let subscriptions = subscription_config
-            .try_deserialize::<Subscriptions>()
-            .map_err(|err| {
-                debug!("{}", err.to_string());
-                ConfigError::Message(format!("配置文件解析出错!"))
-            })?;
+        let daemon_state_file = subscriptions.daemon.state_file.clone().unwrap_or_else(|| {
+            let mut path = dirs::data_dir().unwrap_or_else(|| std::env::current_dir().unwrap());
+            path.push("ov-bilidown");
+            path.push("daemon_state.json");
+            path
+        });
+
+        let queue_file = {
+            let mut path = dirs::data_dir().unwrap_or_else(|| std::env::current_dir().unwrap());
+            path.push("ov-bilidown");
+            path.push("queue.json");
+            path
+        };
 
         Ok(Self {
-            bvid: cli.bvid,
             output_dir,
             cookie_file,
-            info_only: cli.info_only,
+            subscription_file,
+            archive_file,
+            wbi_cache_file,
+            identity_file,
+            daemon_state_file,
+            queue_file,
+            anonymous: cli.anonymous,
+            endpoints: subscriptions.endpoints,
+            limits: subscriptions.limits,
+            daemon: subscriptions.daemon,
+            server: subscriptions.server,
+            defaults,
+            overrides,
             subscriptions: subscriptions.subscriptions,
+            included_files: subscriptions.included_files,
         })
     }
+
+    /// 重新读取订阅文件（包括被包含的文件）中的订阅并填入默认值，其余配置保持不变
+    pub fn reload_subscriptions(&mut self) -> std::result::Result<(), ConfigError> {
+        let subscriptions = load_subscriptions(&self.subscription_file)?;
+        self.subscriptions = subscriptions.subscriptions;
+        self.included_files = subscriptions.included_files;
+        for subscription in &mut self.subscriptions {
+            self.defaults.apply(subscription);
+            self.overrides.apply(subscription);
+        }
+        Ok(())
+    }
+}
+
+/// 读取订阅文件及其包含的文件中的全部订阅，不填入默认值
+pub fn read_subscriptions(path: &Path) -> std::result::Result<Vec<Subscription>, ConfigError> {
+    Ok(load_subscriptions(path)?.subscriptions)
+}
+
+/// 加载订阅文件并应用环境变量，随后按顺序追加被包含文件中的订阅
+fn load_subscriptions(path: &Path) -> std::result::Result<Subscriptions, ConfigError> {
+    let mut subscriptions =
+        load_with_environment(path, environment()).map_err(|err| located(path, err))?;
+    let mut loaded = vec![canonical(path)];
+    let include = subscriptions.include.clone();
+    load_includes(
+        path,
+        &include,
+        &mut subscriptions.subscriptions,
+        &mut loaded,
+    )?;
+    subscriptions.included_files = loaded.split_off(1);
+    Ok(subscriptions)
+}
+
+/// 递归加载被包含的文件，已加载过的文件会被跳过，避免循环包含
+fn load_includes(
+    parent: &Path,
+    include: &[PathBuf],
+    subscriptions: &mut Vec<Subscription>,
+    loaded: &mut Vec<PathBuf>,
+) -> std::result::Result<(), ConfigError> {
+    for path in include {
+        let path = include_path(parent, path);
+        if loaded.contains(&canonical(&path)) {
+            warn!("订阅文件 {} 已被包含，跳过", path.display());
+            continue;
+        }
+        loaded.push(canonical(&path));
+        let included = parse_included(&path).map_err(|err| located(&path, err))?;
+        debug!(
+            "已包含订阅文件 {}，{} 个订阅",
+            path.display(),
+            included.subscriptions.len()
+        );
+        subscriptions.extend(included.subscriptions);
+        load_includes(&path, &included.include, subscriptions, loaded)?;
+    }
+    Ok(())
+}
+
+/// `include` 中的相对路径相对于包含它的文件所在目录
+pub(crate) fn include_path(parent: &Path, path: &Path) -> PathBuf {
+    parent.parent().unwrap_or(Path::new("")).join(path)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// 出错时在错误信息中指出文件与行号
+fn located(path: &Path, err: ConfigError) -> ConfigError {
+    debug!("{:?}", err);
+    let location = match crate::lint::error_line(path, &err) {
+        Some(line) => format!("{}:{}", path.display(), line),
+        None => path.display().to_string(),
+    };
+    ConfigError::Message(format!("订阅文件 {} 解析出错: {}", location, err))
+}
+
+/// 只解析订阅文件本身，不应用环境变量，也不加载被包含的文件
+pub(crate) fn parse_subscriptions(path: &Path) -> std::result::Result<Subscriptions, ConfigError> {
+    config::Config::builder()
+        .add_source(config::File::from(path.to_path_buf()).required(false))
+        .build()?
+        .try_deserialize::<Subscriptions>()
+}
+
+/// 解析被包含的订阅文件，文件必须存在
+pub(crate) fn parse_included(
+    path: &Path,
+) -> std::result::Result<IncludedSubscriptions, ConfigError> {
+    config::Config::builder()
+        .add_source(config::File::from(path.to_path_buf()))
+        .build()?
+        .try_deserialize::<IncludedSubscriptions>()
+}
+
+/// 形如 `OV_BILIDOWN_DEFAULTS__OUTPUT_DIR` 的环境变量，覆盖配置文件中的同名配置项
+fn environment() -> config::Environment {
+    config::Environment::with_prefix("OV_BILIDOWN")
+        .prefix_separator("_")
+        .separator("__")
+}
+
+fn load_with_environment(
+    path: &Path,
+    environment: config::Environment,
+) -> std::result::Result<Subscriptions, ConfigError> {
+    config::Config::builder()
+        .add_source(config::File::from(path.to_path_buf()).required(false))
+        .add_source(environment)
+        .build()?
+        .try_deserialize::<Subscriptions>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_environment_overrides_file_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "[defaults]\nformat = \"mp3\"\nquality = \"192k\"\nartist = \"{artist}\"\n\n\
+             [[sub]]\nbvid = \"BV1fk4y1x7Aa\"\n\n\
+             [[sub]]\nbvid = \"BV1nA4y1x7Ab\"\nquality = \"hires\"\nartist = \"洛天依\"\n",
+        )
+        .unwrap();
+        let environment = environment().source(Some(
+            [
+                ("OV_BILIDOWN_DEFAULTS__FORMAT", "flac"),
+                ("OV_BILIDOWN_DEFAULTS__CONCURRENCY", "8"),
+                ("UNRELATED", "1"),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+        ));
+
+        let loaded = load_with_environment(&path, environment).unwrap();
+        let defaults = loaded.defaults;
+        assert_eq!(defaults.format, Some(AudioFormat::Flac));
+        assert_eq!(defaults.quality, Some(AudioQuality::Q192K));
+        assert_eq!(defaults.concurrency, Some(8));
+
+        let mut subscriptions = loaded.subscriptions;
+        for subscription in &mut subscriptions {
+            defaults.apply(subscription);
+        }
+        assert_eq!(subscriptions[0].format, Some(AudioFormat::Flac));
+        assert_eq!(subscriptions[0].quality, Some(AudioQuality::Q192K));
+        assert_eq!(subscriptions[0].artist.as_deref(), Some("{artist}"));
+        assert_eq!(subscriptions[1].quality, Some(AudioQuality::HiRes));
+        assert_eq!(subscriptions[1].artist.as_deref(), Some("洛天依"));
+
+        // 命令行中的格式与音质优先于订阅中的单独设置
+        let overrides = Overrides {
+            format: Some(AudioFormat::Mp3),
+            quality: None,
+        };
+        overrides.apply(&mut subscriptions[1]);
+        assert_eq!(subscriptions[1].format, Some(AudioFormat::Mp3));
+        assert_eq!(subscriptions[1].quality, Some(AudioQuality::HiRes));
+    }
+
+    #[test]
+    fn test_includes_are_loaded_once_and_selected_by_name_or_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::create_dir(dir.path().join("team")).unwrap();
+        std::fs::write(
+            &path,
+            "include = [\"team/alice.toml\"]\n\n\
+             [[sub]]\nbvid = \"BV1fk4y1x7Aa\"\nname = \"合集\"\n",
+        )
+        .unwrap();
+        // 循环包含的文件只加载一次
+        std::fs::write(
+            dir.path().join("team/alice.toml"),
+            "include = [\"../config.toml\", \"bob.toml\"]\n\n\
+             [[sub]]\nbvid = \"BV1nA4y1x7Ab\"\ntags = [\"vocaloid\", \"alice\"]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("team/bob.toml"),
+            "[[sub]]\nbvid = \"BV1xx411c7Xd\"\ntags = [\"bob\"]\n",
+        )
+        .unwrap();
+
+        let loaded = load_subscriptions(&path).unwrap();
+        let bvids: Vec<_> = loaded
+            .subscriptions
+            .iter()
+            .map(|subscription| subscription.bvid.as_str())
+            .collect();
+        assert_eq!(bvids, ["BV1fk4y1x7Aa", "BV1nA4y1x7Ab", "BV1xx411c7Xd"]);
+        assert_eq!(loaded.included_files.len(), 2);
+
+        let select = |only: &[&str], skip: &[&str]| {
+            let select = SelectArgs {
+                only: only.iter().map(|s| s.to_string()).collect(),
+                skip: skip.iter().map(|s| s.to_string()).collect(),
+            };
+            loaded
+                .subscriptions
+                .iter()
+                .filter(|subscription| select.selects(subscription))
+                .map(|subscription| subscription.bvid.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(select(&[], &[]).len(), 3);
+        assert_eq!(
+            select(&["合集", "bob"], &[]),
+            ["BV1fk4y1x7Aa", "BV1xx411c7Xd"]
+        );
+        assert_eq!(select(&[], &["vocaloid"]), ["BV1fk4y1x7Aa", "BV1xx411c7Xd"]);
+        assert_eq!(select(&["alice", "bob"], &["bob"]), ["BV1nA4y1x7Ab"]);
+    }
+
+    #[test]
+    fn test_missing_include_reports_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "include = [\"missing.toml\"]\n").unwrap();
+        let err = load_subscriptions(&path).unwrap_err().to_string();
+        assert!(err.contains("missing.toml"), "{}", err);
+    }
 }
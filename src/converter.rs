@@ -1,9 +1,11 @@
 use std::path::{Path, PathBuf};
 
 use chrono::Datelike;
+use clap::ValueEnum;
 use lofty::config::WriteOptions;
+use lofty::picture::{Picture, PictureType};
 use lofty::prelude::*;
-use lofty::tag::{Tag, TagType};
+use lofty::tag::{ItemKey, Tag, TagType};
 
 use crate::{
     download::DashAudioStream,
@@ -12,7 +14,22 @@ use crate::{
     subscription::Subscription,
     utils,
 };
-use log::{debug, info};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+/// 模板中可用的通配符
+pub const WILDCARDS: &[&str] = &[
+    "title",
+    "part_title",
+    "artist",
+    "uploader",
+    "album",
+    "bv_id",
+    "aid",
+    "duration",
+    "page",
+    "date",
+];
 
 fn replace_wildcards(input: &str, video_info: &VideoBasicInfo, video_part: &VideoPart) -> String {
     let mut result = input.to_string();
@@ -35,7 +52,70 @@ fn replace_wildcards(input: &str, video_info: &VideoBasicInfo, video_part: &Vide
     result
 }
 
-pub async fn convert_audio_with_metadata(
+/// 按订阅模板渲染后的音轨标签
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrackTags {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+}
+
+impl TrackTags {
+    /// 优先使用订阅中的模板，未配置时依次使用分P标题、UP主名与视频标题
+    pub fn render(
+        video_info: &VideoBasicInfo,
+        video_part: &VideoPart,
+        subscription: &Subscription,
+    ) -> Self {
+        let render = |template: &Option<String>, default: &str| match template {
+            Some(template) => replace_wildcards(template, video_info, video_part),
+            None => default.to_string(),
+        };
+        Self {
+            title: render(&subscription.title, &video_part.part),
+            artist: render(&subscription.artist, &video_info.owner.name),
+            album: render(&subscription.album, &video_info.title),
+        }
+    }
+}
+
+/// 未设置文件名模板时的输出文件名（不含扩展名）
+pub const DEFAULT_FILENAME: &str = "{title}-P{page}";
+
+/// 音频流转换后的输出格式与文件路径
+pub fn output_path(
+    output_dir: &Path,
+    video_info: &VideoBasicInfo,
+    video_part: &VideoPart,
+    audio_stream: &DashAudioStream,
+    subscription: &Subscription,
+) -> (AudioFormat, PathBuf) {
+    let format = output_format(audio_stream, subscription);
+    let filename = generate_output_filename(video_info, video_part, subscription, format.clone());
+    (format, output_dir.join(filename))
+}
+
+/// 分P序号变化后已有输出文件在 `output_dir` 下的新路径，扩展名保持不变
+pub fn renumbered_path(
+    path: &Path,
+    output_dir: &Path,
+    video_info: &VideoBasicInfo,
+    video_part: &VideoPart,
+    subscription: &Subscription,
+) -> PathBuf {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("mp3");
+    output_dir.join(format!(
+        "{}.{}",
+        render_filename(video_info, video_part, subscription).display(),
+        extension
+    ))
+}
+
+/// 将原始音频转换为输出格式，返回输出文件路径，不写入元数据
+pub async fn convert_audio(
     input_path: &Path,
     output_dir: &Path,
     video_info: &VideoBasicInfo,
@@ -43,34 +123,40 @@ pub async fn convert_audio_with_metadata(
     audio_stream: &DashAudioStream,
     subscription: &Subscription,
 ) -> Result<PathBuf> {
-    info!("开始转换音频并添加元数据: {:?}", input_path);
+    info!("开始转换音频: {:?}", input_path);
 
     // 确定输出格式
-    let output_format = determine_output_format(audio_stream);
-    let output_filename =
-        generate_output_filename(&video_info.title, video_part, output_format.clone());
-    let output_path = output_dir.join(output_filename);
+    let (output_format, output_path) = output_path(
+        output_dir,
+        video_info,
+        video_part,
+        audio_stream,
+        subscription,
+    );
+
+    // 文件名模板可能包含子目录
+    if let Some(parent) = output_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
 
     // 转换音频格式
     match output_format {
-        AudioFormat::Mp3 => convert_to_mp3(input_path, &output_path).await?,
+        AudioFormat::Mp3 => convert_to_mp3(input_path, &output_path, subscription.bitrate).await?,
         AudioFormat::Flac => convert_to_flac(input_path, &output_path).await?,
     }
 
-    // 添加元数据
-    add_metadata_to_file(&output_path, video_info, video_part, subscription)?;
-
     info!("音频转换完成: {:?}", output_path);
     Ok(output_path)
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
 pub enum AudioFormat {
     Mp3,
     Flac,
 }
 
-fn determine_output_format(audio_stream: &DashAudioStream) -> AudioFormat {
+pub(crate) fn determine_output_format(audio_stream: &DashAudioStream) -> AudioFormat {
     if audio_stream.mime_type.contains("aac")
         || audio_stream.mime_type.contains("mp4")
         || audio_stream.mime_type.contains("m4a")
@@ -82,17 +168,66 @@ fn determine_output_format(audio_stream: &DashAudioStream) -> AudioFormat {
     }
 }
 
-fn generate_output_filename(title: &str, video_part: &VideoPart, format: AudioFormat) -> String {
-    let clean_title = utils::sanitize_filename(title);
+/// 订阅指定的输出格式，未指定时按音频流决定
+pub(crate) fn output_format(
+    audio_stream: &DashAudioStream,
+    subscription: &Subscription,
+) -> AudioFormat {
+    subscription
+        .format
+        .clone()
+        .unwrap_or_else(|| determine_output_format(audio_stream))
+}
+
+/// 按订阅的文件名模板渲染相对路径（不含扩展名），模板中的 `/` 分隔子目录，
+/// 每一级分别清理非法字符，空目录名与 `.`、`..` 会被忽略
+///
+/// 多P视频的模板中没有 `{page}` 时，各分P会渲染出同一路径而互相覆盖，因此在文件名末尾追加 `-P<序号>`
+fn render_filename(
+    video_info: &VideoBasicInfo,
+    video_part: &VideoPart,
+    subscription: &Subscription,
+) -> PathBuf {
+    let template = subscription.filename.as_deref().unwrap_or(DEFAULT_FILENAME);
+    let mut components: Vec<String> = template
+        .split('/')
+        .map(|component| {
+            utils::sanitize_filename(&replace_wildcards(component, video_info, video_part))
+        })
+        .filter(|component| !component.is_empty() && component != "." && component != "..")
+        .collect();
+    let multi_page = video_info
+        .pages
+        .as_ref()
+        .is_some_and(|pages| pages.len() > 1);
+    if multi_page && !template.contains("{page}") {
+        match components.last_mut() {
+            Some(name) => name.push_str(&format!("-P{}", video_part.page)),
+            None => components.push(format!("P{}", video_part.page)),
+        }
+    }
+    components.iter().collect()
+}
+
+fn generate_output_filename(
+    video_info: &VideoBasicInfo,
+    video_part: &VideoPart,
+    subscription: &Subscription,
+    format: AudioFormat,
+) -> PathBuf {
     let extension = match format {
         AudioFormat::Mp3 => "mp3",
         AudioFormat::Flac => "flac",
     };
 
-    format!("{}-P{}.{}", clean_title, video_part.page, extension)
+    PathBuf::from(format!(
+        "{}.{}",
+        render_filename(video_info, video_part, subscription).display(),
+        extension
+    ))
 }
 
-async fn convert_to_mp3(input_path: &Path, output_path: &Path) -> Result<()> {
+async fn convert_to_mp3(input_path: &Path, output_path: &Path, bitrate: Option<u32>) -> Result<()> {
     info!("正在转换为MP3格式: {:?} -> {:?}", input_path, output_path);
 
     let input_path_str = input_path
@@ -103,14 +238,20 @@ async fn convert_to_mp3(input_path: &Path, output_path: &Path) -> Result<()> {
         .to_str()
         .ok_or_else(|| BilidownError::ConversionError("输出路径无效".to_string()))?;
 
+    // 指定码率时使用 CBR，否则使用高质量 VBR
+    let quality = match bitrate {
+        Some(bitrate) => ["-b:a".to_string(), format!("{}k", bitrate)],
+        None => ["-q:a".to_string(), "2".to_string()],
+    };
+
     // 使用ffmpeg命令行工具进行转换
     utils::run_ffmpeg_command(&[
         "-i",
         input_path_str,
         "-codec:a",
         "libmp3lame",
-        "-q:a",
-        "2",  // 高质量
+        &quality[0],
+        &quality[1],
         "-y", // 覆盖输出文件
         output_path_str,
     ])
@@ -142,12 +283,13 @@ async fn convert_to_flac(input_path: &Path, output_path: &Path) -> Result<()> {
     .await
 }
 
-fn add_metadata_to_file(
+/// 按订阅模板写入标题、艺术家、专辑等元数据，返回写入的标签
+pub fn add_metadata_to_file(
     file_path: &Path,
     video_info: &VideoBasicInfo,
     video_part: &VideoPart,
     subscription: &Subscription,
-) -> Result<()> {
+) -> Result<TrackTags> {
     info!("正在添加元数据到文件: {:?}", file_path);
 
     // 确保文件存在并可以访问
@@ -160,7 +302,7 @@ fn add_metadata_to_file(
 
     // 读取现有的音频文件
     let mut tagged_file = lofty::read_from_path(file_path)
-        .map_err(|e| BilidownError::IoError(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
+        .map_err(|e| BilidownError::IoError(std::io::Error::other(e)))?;
 
     // 获取或创建标签
     let tag = if let Some(existing_tag) = tagged_file.tag_mut(TagType::Id3v2) {
@@ -183,31 +325,12 @@ fn add_metadata_to_file(
     };
 
     // 设置元数据 - 优先使用订阅中的自定义元数据，否则使用视频信息
-    // 歌曲名：优先使用订阅中的track_title，否则使用分P标题
-    let title = if let Some(ref title) = subscription.title {
-        replace_wildcards(title, video_info, video_part)
-    } else {
-        video_part.part.clone()
-    };
-    debug!("title: {}", title);
-    tag.set_title(title);
-
-    // 艺术家：优先使用订阅中的artist，否则使用UP主名
-    let artist = if let Some(ref artist) = subscription.artist {
-        replace_wildcards(artist, video_info, video_part)
-    } else {
-        video_info.owner.name.clone()
-    };
-    debug!("artist: {}", artist);
-    tag.set_artist(artist);
-
-    // 专辑名：优先使用订阅中的album，否则使用视频标题
-    let album = if let Some(ref album) = subscription.album {
-        replace_wildcards(album, video_info, video_part)
-    } else {
-        video_info.title.clone()
-    };
-    tag.set_album(album);
+    let tags = TrackTags::render(video_info, video_part, subscription);
+    debug!("title: {}", tags.title);
+    debug!("artist: {}", tags.artist);
+    tag.set_title(tags.title.clone());
+    tag.set_artist(tags.artist.clone());
+    tag.set_album(tags.album.clone());
 
     tag.set_genre("Bilibili".to_string());
     tag.set_year(chrono::Local::now().year() as u32);
@@ -218,13 +341,89 @@ fn add_metadata_to_file(
         tag.set_comment(video_info.desc.clone());
     }
 
+    // 订阅中配置的额外标签，可覆盖上面的默认值
+    for (key, template) in &subscription.extra_tags {
+        let Some(item_key) = extra_tag_key(key) else {
+            warn!("不支持的标签 {}，已忽略", key);
+            continue;
+        };
+        tag.insert_text(
+            item_key,
+            replace_wildcards(template, video_info, video_part),
+        );
+    }
+
     info!("元数据准备完成，正在写入文件...");
 
     tagged_file
         .save_to_path(file_path, WriteOptions::default())
-        .map_err(|e| BilidownError::IoError(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
+        .map_err(|e| BilidownError::IoError(std::io::Error::other(e)))?;
 
     debug!("元数据添加完成");
+    Ok(tags)
+}
+
+/// `extra_tags` 中可用的标签名
+pub const EXTRA_TAG_KEYS: &[&str] = &[
+    "genre",
+    "album_artist",
+    "composer",
+    "lyricist",
+    "comment",
+    "year",
+    "date",
+    "publisher",
+    "language",
+];
+
+fn extra_tag_key(key: &str) -> Option<ItemKey> {
+    Some(match key {
+        "genre" => ItemKey::Genre,
+        "album_artist" => ItemKey::AlbumArtist,
+        "composer" => ItemKey::Composer,
+        "lyricist" => ItemKey::Lyricist,
+        "comment" => ItemKey::Comment,
+        "year" => ItemKey::Year,
+        "date" => ItemKey::RecordingDate,
+        "publisher" => ItemKey::Publisher,
+        "language" => ItemKey::Language,
+        _ => return None,
+    })
+}
+
+/// 将封面图片与歌词嵌入音频文件，需在写入元数据之后调用
+pub fn embed_cover_and_lyrics(
+    file_path: &Path,
+    cover: Option<&[u8]>,
+    lyrics: Option<&str>,
+) -> Result<()> {
+    if cover.is_none() && lyrics.is_none() {
+        return Ok(());
+    }
+
+    let mut tagged_file = lofty::read_from_path(file_path)
+        .map_err(|e| BilidownError::IoError(std::io::Error::other(e)))?;
+    let tag_type = tagged_file.primary_tag_type();
+    if tagged_file.tag(tag_type).is_none() {
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+    let tag = tagged_file.tag_mut(tag_type).unwrap();
+
+    if let Some(cover) = cover {
+        let mut picture = Picture::from_reader(&mut &cover[..])
+            .map_err(|e| BilidownError::ConversionError(format!("封面图片无效: {}", e)))?;
+        picture.set_pic_type(PictureType::CoverFront);
+        tag.remove_picture_type(PictureType::CoverFront);
+        tag.push_picture(picture);
+    }
+    if let Some(lyrics) = lyrics {
+        tag.insert_text(ItemKey::Lyrics, lyrics.to_string());
+    }
+
+    tagged_file
+        .save_to_path(file_path, WriteOptions::default())
+        .map_err(|e| BilidownError::IoError(std::io::Error::other(e)))?;
+    debug!("已嵌入封面与歌词: {:?}", file_path);
     Ok(())
 }
 
@@ -0,0 +1,278 @@
+//! Long-running mode that periodically syncs subscriptions
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Local};
+use futures::future;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use crate::{
+    archive::Archive,
+    config::AppConfig,
+    error::Result,
+    queue::JobQueue,
+    report::{DownloadReport, RunSummary},
+    subscription::Subscription,
+    user::User,
+    utils,
+};
+
+/// 检查订阅文件是否修改与是否有订阅到期的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 守护模式设置，对应配置文件中的 `[daemon]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// 两次同步之间的间隔（秒），订阅可通过 `interval` 单独设置
+    pub interval: u64,
+    /// 每次计划同步时额外增加 0 到 `jitter` 秒的随机延迟，避免同时请求
+    pub jitter: u64,
+    /// 状态文件路径，默认 `~/.local/share/ov-bilidown/daemon_state.json`
+    pub state_file: Option<PathBuf>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            interval: 3600,
+            jitter: 300,
+            state_file: None,
+        }
+    }
+}
+
+/// 单个订阅最近一次同步的结果
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubscriptionState {
+    pub last_run: DateTime<Local>,
+    pub next_run: DateTime<Local>,
+    pub summary: RunSummary,
+}
+
+/// 守护模式的状态文件，以订阅的 [`Subscription::key`] 为键记录每个订阅的同步时间与结果
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DaemonState {
+    pub last_run: Option<DateTime<Local>>,
+    pub subscriptions: BTreeMap<String, SubscriptionState>,
+}
+
+impl DaemonState {
+    /// 读取状态文件，文件不存在或无法解析时返回空状态
+    pub fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// 先写临时文件再替换，中断时不会丢失已有的同步计划
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        utils::write_atomic(path, serde_json::to_string_pretty(self)?).await
+    }
+
+    /// 从未同步过或已到计划时间的订阅需要同步
+    fn is_due(&self, subscription: &Subscription, now: DateTime<Local>) -> bool {
+        self.subscriptions
+            .get(&subscription.key())
+            .is_none_or(|state| state.next_run <= now)
+    }
+}
+
+/// 常驻运行，按间隔同步到期的订阅，直到收到 Ctrl-C
+///
+/// 整个运行期间共用一个 `User`，每轮同步前检查登录状态；订阅文件修改后在下一次检查时重新加载。
+pub async fn run(config: &mut AppConfig, user: &mut User) -> Result<()> {
+    let archive = Archive::load(&config.archive_file)?;
+    let queue = JobQueue::load(&config.queue_file)?;
+    let _attached = queue.attach(user.progress());
+    let mut state = DaemonState::load(&config.daemon_state_file);
+    let mut modified = modified_times(config);
+    // 提前注册 Ctrl-C 监听，同步过程中收到信号也能退出
+    let mut shutdown = tokio::spawn(tokio::signal::ctrl_c());
+    info!(
+        "守护模式已启动，同步间隔 {} 秒，状态文件: {}",
+        config.daemon.interval,
+        config.daemon_state_file.display()
+    );
+
+    loop {
+        if modified_times(config) != modified {
+            match config.reload_subscriptions() {
+                Ok(()) => info!(
+                    "订阅文件已修改，重新加载 {} 个订阅",
+                    config.subscriptions.len()
+                ),
+                Err(e) => warn!("重新加载订阅文件失败: {}，继续使用之前的订阅", e),
+            }
+            // 被包含的文件可能随之变化
+            modified = modified_times(config);
+        }
+
+        let now = Local::now();
+        let due: Vec<&Subscription> = config
+            .subscriptions
+            .iter()
+            .filter(|subscription| state.is_due(subscription, now))
+            .collect();
+        if !due.is_empty() {
+            info!("开始同步 {} 个到期的订阅", due.len());
+            user.refresh_session(config).await;
+            let (user, config) = (&*user, &*config);
+            let tasks = due.iter().map(|subscription| {
+                let (archive, queue) = (&archive, &queue);
+                async move {
+                    match queue
+                        .download(subscription, user, &config.output_dir, Some(archive))
+                        .await
+                    {
+                        Ok(report) => report,
+                        Err(e) => {
+                            warn!("订阅 {} 处理失败: {}", subscription.bvid, e);
+                            DownloadReport::failed(&subscription.bvid, e)
+                        }
+                    }
+                }
+            });
+            let reports = tokio::select! {
+                reports = future::join_all(tasks) => reports,
+                _ = &mut shutdown => {
+                    info!("收到退出信号，中断本轮同步");
+                    return Ok(());
+                }
+            };
+
+            let finished = Local::now();
+            let mut total = RunSummary::default();
+            for (subscription, report) in due.iter().zip(&reports) {
+                let mut summary = RunSummary::default();
+                summary.add_download(report);
+                total.add_download(report);
+                let next_run = next_run(
+                    finished,
+                    subscription,
+                    config.daemon.interval,
+                    config.daemon.jitter,
+                );
+                debug!("订阅 {} 下次同步时间: {}", subscription.bvid, next_run);
+                state.subscriptions.insert(
+                    subscription.key(),
+                    SubscriptionState {
+                        last_run: finished,
+                        next_run,
+                        summary,
+                    },
+                );
+            }
+            info!(
+                "本轮同步完成: 新下载 {} 个，跳过 {} 个，不可用 {} 个，失败 {} 个",
+                total.downloaded,
+                total.skipped,
+                total.unavailable,
+                total.failures.len()
+            );
+            for failure in &total.failures {
+                warn!(
+                    "  - {} P{}: {}",
+                    failure.bvid,
+                    failure.page.unwrap_or_default(),
+                    failure.error
+                );
+            }
+
+            // 只保留订阅文件中仍存在的订阅
+            state.subscriptions.retain(|key, _| {
+                config
+                    .subscriptions
+                    .iter()
+                    .any(|subscription| &subscription.key() == key)
+            });
+            state.last_run = Some(finished);
+            if let Err(e) = state.save(&config.daemon_state_file).await {
+                warn!("保存状态文件失败: {}", e);
+            }
+        }
+
+        tokio::select! {
+            _ = sleep(POLL_INTERVAL) => {}
+            _ = &mut shutdown => {
+                info!("收到退出信号，守护模式结束");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// 下次同步时间：订阅自身的间隔或全局间隔，加上随机延迟
+fn next_run(
+    from: DateTime<Local>,
+    subscription: &Subscription,
+    interval: u64,
+    jitter: u64,
+) -> DateTime<Local> {
+    let interval = subscription.interval.unwrap_or(interval);
+    let jitter = if jitter > 0 {
+        rand::random_range(0..=jitter)
+    } else {
+        0
+    };
+    from + Duration::from_secs(interval + jitter)
+}
+
+/// 订阅文件及其包含的文件的修改时间
+fn modified_times(config: &AppConfig) -> Vec<Option<SystemTime>> {
+    std::iter::once(&config.subscription_file)
+        .chain(&config.included_files)
+        .map(|path| {
+            std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_run_uses_subscription_interval() {
+        let now = Local::now();
+        let subscription = Subscription {
+            bvid: "BV1fk4y1x7Aa".to_string(),
+            interval: Some(60),
+            ..Default::default()
+        };
+        assert_eq!(
+            next_run(now, &subscription, 3600, 0),
+            now + Duration::from_secs(60)
+        );
+
+        let subscription = Subscription {
+            bvid: "BV1fk4y1x7Aa".to_string(),
+            ..Default::default()
+        };
+        let next = next_run(now, &subscription, 3600, 30);
+        assert!(next >= now + Duration::from_secs(3600));
+        assert!(next <= now + Duration::from_secs(3630));
+
+        let mut state = DaemonState::default();
+        assert!(state.is_due(&subscription, now));
+        state.subscriptions.insert(
+            subscription.key(),
+            SubscriptionState {
+                last_run: now,
+                next_run: next,
+                summary: RunSummary::default(),
+            },
+        );
+        assert!(!state.is_due(&subscription, now));
+        assert!(state.is_due(&subscription, next));
+    }
+}
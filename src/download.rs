@@ -1,13 +1,25 @@
 use crate::{api::endpoints, error::Result, user::User};
+use clap::ValueEnum;
 use log::{debug, trace};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// 音质等级，配置文件与命令行中写作 `64k`、`132k`、`192k`、`dolby` 或 `hires`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ValueEnum)]
 pub enum AudioQuality {
+    #[serde(rename = "64k")]
+    #[value(name = "64k")]
     Q64K = 1,
+    #[serde(rename = "132k")]
+    #[value(name = "132k")]
     Q132K = 2,
+    #[serde(rename = "192k")]
+    #[value(name = "192k")]
     Q192K = 3,
+    #[serde(rename = "dolby")]
+    #[value(name = "dolby")]
     DolbyAtmos = 4,
+    #[serde(rename = "hires")]
+    #[value(name = "hires")]
     HiRes = 5,
 }
 
@@ -32,6 +44,16 @@ impl AudioQuality {
             Self::HiRes => "Hi-Res无损",
         }
     }
+
+    /// 该音质是否需要登录才能获取（游客最高只能获取 132K）
+    pub fn requires_login(&self) -> bool {
+        *self >= Self::Q192K
+    }
+
+    /// 该音质是否需要大会员（杜比全景声与 Hi-Res 无损）
+    pub fn requires_vip(&self) -> bool {
+        matches!(self, Self::DolbyAtmos | Self::HiRes)
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -79,6 +101,22 @@ impl DashAudioStream {
             .max_by_key(|stream| stream.get_quality().map(|q| q as u32).unwrap_or(0))
     }
 
+    /// 按音质上限选择音频流：不超过上限的最高音质，全部超过上限时选择最低音质
+    pub fn select(
+        streams: &[DashAudioStream],
+        max_quality: Option<AudioQuality>,
+    ) -> Option<&DashAudioStream> {
+        let Some(max_quality) = max_quality else {
+            return Self::get_highest_quality(streams);
+        };
+        let level = |stream: &&DashAudioStream| stream.get_quality().map(|q| q as u32).unwrap_or(0);
+        streams
+            .iter()
+            .filter(|stream| level(stream) <= max_quality as u32)
+            .max_by_key(level)
+            .or_else(|| streams.iter().min_by_key(level))
+    }
+
     /// 从音频流列表中获取最高码率的音频流
     #[allow(dead_code)]
     pub fn get_highest_bandwidth(streams: &[DashAudioStream]) -> Option<&DashAudioStream> {
@@ -103,8 +141,8 @@ pub struct DolbyInfo {
 #[derive(Debug, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct FlacInfo {
-    pub display: bool, // 是否在播放器显示切换Hi-Res无损音轨按钮
-    pub audio: DashAudioStream,
+    pub display: bool,                  // 是否在播放器显示切换Hi-Res无损音轨按钮
+    pub audio: Option<DashAudioStream>, // 未登录时为 null
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -131,28 +169,130 @@ pub struct PlayUrlDashData {
     pub dash: DashInfo,
 }
 
+/// 分P的音频流列表
+#[derive(Debug, Clone, Default)]
+pub struct AudioStreams {
+    pub streams: Vec<DashAudioStream>,
+    /// 接口标记存在但未返回地址的音轨（游客请求杜比或无损音轨时）
+    pub withheld: Option<AudioQuality>,
+}
+
+impl AudioStreams {
+    /// 所选音频流是否因未登录而低于可获取的最高音质
+    ///
+    /// 仅当列出或被拒绝的音轨中存在需要登录、高于所选音质且不超过音质上限的音轨时成立
+    pub fn limited_by_login(
+        &self,
+        selected: &DashAudioStream,
+        max_quality: Option<AudioQuality>,
+    ) -> bool {
+        let selected = selected.get_quality();
+        self.streams
+            .iter()
+            .filter_map(DashAudioStream::get_quality)
+            .chain(self.withheld)
+            .any(|quality| {
+                quality.requires_login()
+                    && selected.is_none_or(|selected| quality > selected)
+                    && max_quality.is_none_or(|max| quality <= max)
+            })
+    }
+}
+
 impl crate::models::VideoPart {
     pub async fn get_dash_audio_stream(
         &self,
         bvid: &str,
         user: &User,
     ) -> Result<Vec<DashAudioStream>> {
+        Ok(self.get_audio_streams(bvid, user).await?.streams)
+    }
+
+    /// 获取音频流，并记录接口标记存在但未返回的音轨
+    pub async fn get_audio_streams(&self, bvid: &str, user: &User) -> Result<AudioStreams> {
         let dash_resp = endpoints::get_play_url_dash(user, bvid, self.cid).await?;
 
         let dash = dash_resp.data.dash;
         trace!("{:#?}", dash);
-        let mut audio_streams = dash.audio.unwrap_or_default();
-        if let Some(dolby) = dash.dolby {
-            if let Some(dolby_stream) = dolby.audio {
-                if !dolby_stream.is_empty() {
-                    audio_streams.extend(dolby_stream);
-                }
-            }
+        let mut streams = AudioStreams {
+            streams: dash.audio.unwrap_or_default(),
+            withheld: None,
+        };
+        match dash.dolby {
+            Some(DolbyInfo {
+                audio: Some(dolby_stream),
+                ..
+            }) if !dolby_stream.is_empty() => streams.streams.extend(dolby_stream),
+            Some(dolby) if dolby.r#type > 0 => streams.withheld = Some(AudioQuality::DolbyAtmos),
+            _ => {}
+        }
+        match dash.flac {
+            Some(FlacInfo {
+                audio: Some(audio), ..
+            }) => streams.streams.push(audio),
+            Some(flac) if flac.display => streams.withheld = Some(AudioQuality::HiRes),
+            _ => {}
         }
-        if let Some(flac) = dash.flac {
-            audio_streams.push(flac.audio.clone());
+        debug!("获取到 {} 个音频流", streams.streams.len());
+        Ok(streams)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(id: u32) -> DashAudioStream {
+        DashAudioStream {
+            id,
+            base_url: String::new(),
+            backup_url: None,
+            bandwidth: 0,
+            mime_type: "audio/mp4".to_string(),
+            codecs: "mp4a.40.2".to_string(),
+            segment_base: None,
+            codecid: 0,
         }
-        debug!("获取到 {} 个音频流", audio_streams.len());
-        Ok(audio_streams)
+    }
+
+    #[test]
+    fn test_select_respects_max_quality() {
+        let streams = [stream(30216), stream(30280), stream(30251), stream(30232)];
+        let selected = |max| DashAudioStream::select(&streams, max).map(|stream| stream.id);
+        assert_eq!(selected(None), Some(30251));
+        assert_eq!(selected(Some(AudioQuality::DolbyAtmos)), Some(30280));
+        assert_eq!(selected(Some(AudioQuality::Q132K)), Some(30232));
+
+        // 全部超过上限时选择最低音质
+        let streams = [stream(30280), stream(30251)];
+        assert_eq!(
+            DashAudioStream::select(&streams, Some(AudioQuality::Q64K)).map(|stream| stream.id),
+            Some(30280)
+        );
+    }
+
+    #[test]
+    fn test_limited_by_login() {
+        let streams = AudioStreams {
+            streams: vec![stream(30216), stream(30232)],
+            withheld: None,
+        };
+        // 只列出了游客可获取的音轨
+        assert!(!streams.limited_by_login(&stream(30232), None));
+
+        let streams = AudioStreams {
+            withheld: Some(AudioQuality::HiRes),
+            ..streams
+        };
+        assert!(streams.limited_by_login(&stream(30232), None));
+        // 音质上限不高于所选音质时不算受限
+        assert!(!streams.limited_by_login(&stream(30232), Some(AudioQuality::Q132K)));
+
+        let streams = AudioStreams {
+            streams: vec![stream(30280), stream(30232)],
+            withheld: None,
+        };
+        assert!(streams.limited_by_login(&stream(30232), None));
+        assert!(!streams.limited_by_login(&stream(30280), None));
     }
 }
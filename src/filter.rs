@@ -0,0 +1,200 @@
+//! Content filters that decide which videos and parts a subscription keeps
+//!
+//! Filters only look at the video information, so excluded parts never cost
+//! a playurl request.
+
+use chrono::{DateTime, Local, NaiveDate};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{BilidownError, Result},
+    models::{VideoBasicInfo, VideoPart},
+};
+
+/// 订阅的过滤条件，对应订阅中的 `[sub.filter]`，未设置的条件不生效
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContentFilter {
+    /// 视频标题需匹配的正则表达式
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title_include: Option<String>,
+    /// 视频标题匹配时排除
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title_exclude: Option<String>,
+    /// 分P标题需匹配的正则表达式
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub part_include: Option<String>,
+    /// 分P标题匹配时排除
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub part_exclude: Option<String>,
+    /// 分P最短时长（秒）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_duration: Option<i32>,
+    /// 分P最长时长（秒）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_duration: Option<i32>,
+    /// 只保留该日期及之后发布的视频
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published_after: Option<NaiveDate>,
+    /// 只保留该日期及之前发布的视频
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published_before: Option<NaiveDate>,
+    /// 只保留这些分区（tid）的视频
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tids: Vec<i32>,
+    /// 排除这些分区（tid）的视频
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude_tids: Vec<i32>,
+    /// 最低播放数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_views: Option<i32>,
+}
+
+impl ContentFilter {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// 编译正则表达式，表达式无效时返回参数错误
+    pub fn compile(&self) -> Result<CompiledFilter<'_>> {
+        Ok(CompiledFilter {
+            filter: self,
+            title_include: compile(&self.title_include)?,
+            title_exclude: compile(&self.title_exclude)?,
+            part_include: compile(&self.part_include)?,
+            part_exclude: compile(&self.part_exclude)?,
+        })
+    }
+}
+
+fn compile(pattern: &Option<String>) -> Result<Option<Regex>> {
+    pattern
+        .as_deref()
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|e| {
+                BilidownError::ArgumentError(format!("正则表达式 {} 无效: {}", pattern, e))
+            })
+        })
+        .transpose()
+}
+
+/// 编译后的过滤条件，返回的 `Some(原因)` 表示被排除
+pub struct CompiledFilter<'a> {
+    filter: &'a ContentFilter,
+    title_include: Option<Regex>,
+    title_exclude: Option<Regex>,
+    part_include: Option<Regex>,
+    part_exclude: Option<Regex>,
+}
+
+impl CompiledFilter<'_> {
+    pub fn video_reason(&self, video: &VideoBasicInfo) -> Option<String> {
+        let filter = self.filter;
+        if let Some(regex) = &self.title_include
+            && !regex.is_match(&video.title)
+        {
+            return Some(format!("标题不匹配 {}", regex));
+        }
+        if let Some(regex) = &self.title_exclude
+            && regex.is_match(&video.title)
+        {
+            return Some(format!("标题匹配排除条件 {}", regex));
+        }
+        if !filter.tids.is_empty() && !filter.tids.contains(&video.tid) {
+            return Some(format!(
+                "分区 {} ({}) 不在允许列表中",
+                video.tname, video.tid
+            ));
+        }
+        if filter.exclude_tids.contains(&video.tid) {
+            return Some(format!("分区 {} ({}) 已排除", video.tname, video.tid));
+        }
+        if let Some(min_views) = filter.min_views
+            && video.stat.view < min_views
+        {
+            return Some(format!("播放数 {} 低于 {}", video.stat.view, min_views));
+        }
+        let published = DateTime::from_timestamp(video.pubdate, 0)
+            .map(|time| time.with_timezone(&Local).date_naive());
+        if let (Some(after), Some(published)) = (filter.published_after, published)
+            && published < after
+        {
+            return Some(format!("发布于 {}，早于 {}", published, after));
+        }
+        if let (Some(before), Some(published)) = (filter.published_before, published)
+            && published > before
+        {
+            return Some(format!("发布于 {}，晚于 {}", published, before));
+        }
+        None
+    }
+
+    pub fn part_reason(&self, part: &VideoPart) -> Option<String> {
+        let filter = self.filter;
+        if let Some(regex) = &self.part_include
+            && !regex.is_match(&part.part)
+        {
+            return Some(format!("分P标题不匹配 {}", regex));
+        }
+        if let Some(regex) = &self.part_exclude
+            && regex.is_match(&part.part)
+        {
+            return Some(format!("分P标题匹配排除条件 {}", regex));
+        }
+        if let Some(min) = filter.min_duration
+            && part.duration < min
+        {
+            return Some(format!("时长 {} 秒，短于 {} 秒", part.duration, min));
+        }
+        if let Some(max) = filter.max_duration
+            && part.duration > max
+        {
+            return Some(format!("时长 {} 秒，长于 {} 秒", part.duration, max));
+        }
+        None
+    }
+
+    /// 视频或分P被排除的原因
+    pub fn reason(&self, video: &VideoBasicInfo, part: &VideoPart) -> Option<String> {
+        self.video_reason(video).or_else(|| self.part_reason(part))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(title: &str, duration: i32) -> VideoPart {
+        serde_json::from_value(serde_json::json!({
+            "cid": 1001, "page": 1, "from": "vupload", "part": title, "duration": duration,
+            "dimension": { "width": 0, "height": 0, "rotate": 0 }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_part_filters() {
+        let filter = ContentFilter {
+            part_exclude: Some("(?i)vlog|公告".to_string()),
+            min_duration: Some(60),
+            max_duration: Some(600),
+            ..Default::default()
+        };
+        let compiled = filter.compile().unwrap();
+        assert!(compiled.part_reason(&part("翻唱 千本樱", 240)).is_none());
+        assert!(compiled.part_reason(&part("日常VLOG", 240)).is_some());
+        assert!(compiled.part_reason(&part("开场", 30)).is_some());
+        assert!(compiled.part_reason(&part("直播回放", 7200)).is_some());
+        assert!(ContentFilter::default().is_empty());
+
+        let invalid = ContentFilter {
+            title_include: Some("(".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            invalid.compile(),
+            Err(BilidownError::ArgumentError(_))
+        ));
+    }
+}
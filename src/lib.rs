@@ -4,11 +4,23 @@
 pub use crate::error::{BilidownError, Result};
 
 pub mod api;
+pub mod archive;
 pub mod config;
 pub mod converter;
+pub mod daemon;
 pub mod download;
 pub mod error;
+pub mod filter;
+pub mod limit;
+pub mod lint;
+pub mod lyrics;
+pub mod mirror;
 pub mod models;
+pub mod playlist;
+pub mod progress;
+pub mod queue;
+pub mod report;
+pub mod server;
 pub mod subscription;
 pub mod user;
 pub mod utils;
@@ -17,3 +29,5 @@ pub mod wbi;
 
 // Re-export commonly used types
 pub use models::VideoBasicInfo;
+pub use progress::{ListenerGuard, Progress, ProgressEvent};
+pub use report::{DownloadReport, TrackResult, TrackStatus};
@@ -0,0 +1,122 @@
+//! Concurrency and request rate limits shared by all downloads
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{Semaphore, SemaphorePermit},
+    time::{Instant, sleep_until},
+};
+
+/// 并发与请求频率限制，对应配置文件中的 `[limits]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitConfig {
+    /// 同时进行的接口请求数
+    pub api_concurrency: usize,
+    /// 同时进行的音频下载数
+    pub download_concurrency: usize,
+    /// 同时运行的 ffmpeg 进程数
+    pub ffmpeg_concurrency: usize,
+    /// 每个接口主机每秒最多发出的请求数，0 表示不限制
+    pub api_rate: f64,
+}
+
+impl Default for LimitConfig {
+    fn default() -> Self {
+        Self {
+            api_concurrency: 4,
+            download_concurrency: 3,
+            ffmpeg_concurrency: 2,
+            api_rate: 5.0,
+        }
+    }
+}
+
+pub struct Limiter {
+    api: Semaphore,
+    download: Semaphore,
+    ffmpeg: Semaphore,
+    interval: Option<Duration>,
+    next_slot: Mutex<HashMap<String, Instant>>, // 每个主机下一次允许请求的时间
+}
+
+impl Limiter {
+    pub fn new(config: &LimitConfig) -> Self {
+        let interval =
+            (config.api_rate > 0.0).then(|| Duration::from_secs_f64(1.0 / config.api_rate));
+        Self {
+            api: Semaphore::new(config.api_concurrency.max(1)),
+            download: Semaphore::new(config.download_concurrency.max(1)),
+            ffmpeg: Semaphore::new(config.ffmpeg_concurrency.max(1)),
+            interval,
+            next_slot: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 等待接口请求的并发名额，并按主机限制请求频率
+    pub async fn api(&self, url: &str) -> SemaphorePermit<'_> {
+        let permit = self.api.acquire().await.expect("semaphore closed");
+        if let Some(interval) = self.interval {
+            let host = Url::parse(url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string))
+                .unwrap_or_default();
+            let slot = {
+                let mut next_slot = self.next_slot.lock().unwrap();
+                let now = Instant::now();
+                let slot = next_slot.get(&host).copied().unwrap_or(now).max(now);
+                next_slot.insert(host, slot + interval);
+                slot
+            };
+            sleep_until(slot).await;
+        }
+        permit
+    }
+
+    /// 等待音频下载的并发名额
+    pub async fn download(&self) -> SemaphorePermit<'_> {
+        self.download.acquire().await.expect("semaphore closed")
+    }
+
+    /// 等待 ffmpeg 进程的并发名额
+    pub async fn ffmpeg(&self) -> SemaphorePermit<'_> {
+        self.ffmpeg.acquire().await.expect("semaphore closed")
+    }
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self::new(&LimitConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_api_rate_is_per_host() {
+        let limiter = Limiter::new(&LimitConfig {
+            api_rate: 2.0,
+            ..Default::default()
+        });
+        let start = Instant::now();
+        for _ in 0..3 {
+            drop(
+                limiter
+                    .api("https://api.bilibili.com/x/web-interface/nav")
+                    .await,
+            );
+        }
+        // 同一主机：0s、0.5s、1s
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+        drop(
+            limiter
+                .api("https://passport.bilibili.com/x/passport-login")
+                .await,
+        );
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+}
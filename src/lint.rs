@@ -0,0 +1,740 @@
+//! Validation of the subscription file with line-accurate diagnostics
+//!
+//! The file is parsed with `toml_edit` so every key keeps its position, then
+//! checked for unknown keys, invalid or duplicate bvids, unknown template
+//! wildcards, unsupported extra tags and invalid filter expressions. Known keys
+//! are taken from the serde derives of the config types, and duplicates are
+//! detected across included files. Type errors come from the same
+//! deserialization used to load the file and are mapped back to their line.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use config::ConfigError;
+use regex::Regex;
+use serde::{
+    Deserialize, Serialize,
+    de::{self, Visitor},
+};
+use toml_edit::{Document, Item, Table};
+
+use crate::{
+    api::client::EndpointConfig,
+    config::{Defaults, IncludedSubscriptions, Subscriptions},
+    converter::{EXTRA_TAG_KEYS, WILDCARDS},
+    daemon::DaemonConfig,
+    filter::ContentFilter,
+    limit::LimitConfig,
+    server::ServerConfig,
+    subscription::Subscription,
+    utils,
+};
+
+/// 支持通配符的模板配置项
+const TEMPLATE_KEYS: [&str; 4] = ["title", "artist", "album", "filename"];
+
+/// 结构体反序列化时接受的配置项，由 serde 派生的实现给出，新增字段时无需在这里同步
+///
+/// 派生的 `Deserialize` 会把字段列表传给 `deserialize_struct`，这里只记录该列表，不读取任何数据。
+fn fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> serde::Deserializer<'de> for FieldNames<'_> {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("不是结构体"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("只读取字段列表"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// 各配置表允许的配置项
+fn section_keys() -> [(&'static str, &'static [&'static str]); 5] {
+    [
+        ("defaults", fields::<Defaults>()),
+        ("endpoints", fields::<EndpointConfig>()),
+        ("limits", fields::<LimitConfig>()),
+        ("daemon", fields::<DaemonConfig>()),
+        ("server", fields::<ServerConfig>()),
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// 无法加载或会导致下载出错
+    Error,
+    /// 可以加载，但很可能不是预期的写法
+    Warning,
+}
+
+/// 订阅文件中的一处问题
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub file: PathBuf, // 问题所在的文件，可能是被包含的文件
+    pub severity: Severity,
+    pub line: Option<usize>,
+    pub field: Option<String>, // 如 `sub[2].artist`
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "第 {} 行: ", line)?;
+        }
+        match self.severity {
+            Severity::Error => write!(f, "错误")?,
+            Severity::Warning => write!(f, "警告")?,
+        }
+        if let Some(field) = &self.field {
+            write!(f, " [{}]", field)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// 检查订阅文件及其包含的文件，按文件与行号顺序返回发现的问题
+pub fn check_subscription_file(path: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut checked = Vec::new();
+    let mut seen = Vec::new();
+    check_file(path, false, &mut checked, &mut seen, &mut diagnostics);
+    diagnostics
+}
+
+fn check_file(
+    path: &Path,
+    included: bool,
+    checked: &mut Vec<PathBuf>,
+    seen: &mut Vec<SeenSubscription>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    checked.push(std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            diagnostics.push(Diagnostic {
+                file: path.to_path_buf(),
+                severity: Severity::Error,
+                line: None,
+                field: None,
+                message: format!("无法读取订阅文件 {}: {}", path.display(), e),
+            });
+            return;
+        }
+    };
+    let mut checker = Checker {
+        path,
+        contents: &contents,
+        seen,
+        diagnostics: Vec::new(),
+    };
+    let doc = match Document::parse(contents.as_str()) {
+        Ok(doc) => doc,
+        Err(e) => {
+            checker.push(
+                Severity::Error,
+                e.span().map(|span| span.start),
+                None,
+                format!("TOML 语法错误: {}", e.message()),
+            );
+            diagnostics.append(&mut checker.diagnostics);
+            return;
+        }
+    };
+    checker.check_document(doc.as_table(), included);
+
+    // 类型错误与缺少的字段以加载订阅时的结果为准
+    let result = match included {
+        true => crate::config::parse_included(path).map(|_| ()),
+        false => crate::config::parse_subscriptions(path).map(|_| ()),
+    };
+    if let Err(e) = result {
+        let key = error_key(&e);
+        checker.push(
+            Severity::Error,
+            key.as_deref().and_then(|key| locate(doc.as_table(), key)),
+            key,
+            e.to_string(),
+        );
+    }
+    let includes = checker.check_includes(doc.as_table());
+    checker
+        .diagnostics
+        .sort_by_key(|diagnostic| diagnostic.line);
+    diagnostics.append(&mut checker.diagnostics);
+
+    for include in includes {
+        let canonical = std::fs::canonicalize(&include).unwrap_or_else(|_| include.clone());
+        if !checked.contains(&canonical) {
+            check_file(&include, true, checked, checker.seen, diagnostics);
+        }
+    }
+}
+
+/// 加载订阅文件出错时的行号，用于在错误信息中指出位置
+pub(crate) fn error_line(path: &Path, error: &ConfigError) -> Option<usize> {
+    let key = error_key(error)?;
+    let contents = std::fs::read_to_string(path).ok()?;
+    let doc = Document::parse(contents.as_str()).ok()?;
+    let offset = locate(doc.as_table(), &key)?;
+    Some(line_of(&contents, offset))
+}
+
+/// 已检查的订阅，同一视频订阅到不同输出目录时不算重复
+struct SeenSubscription {
+    bvid: String,
+    output_dir: Option<String>,
+    file: PathBuf,
+    index: usize,
+}
+
+struct Checker<'a> {
+    path: &'a Path,
+    contents: &'a str,
+    seen: &'a mut Vec<SeenSubscription>, // 包括此前检查过的文件中的订阅
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn push(
+        &mut self,
+        severity: Severity,
+        offset: Option<usize>,
+        field: Option<String>,
+        message: String,
+    ) {
+        self.diagnostics.push(Diagnostic {
+            file: self.path.to_path_buf(),
+            severity,
+            line: offset.map(|offset| line_of(self.contents, offset)),
+            field,
+            message,
+        });
+    }
+
+    fn check_document(&mut self, root: &Table, included: bool) {
+        // 被包含的订阅文件中只读取订阅与嵌套的包含
+        if included {
+            self.check_keys(root, None, fields::<IncludedSubscriptions>());
+        } else {
+            self.check_keys(root, None, fields::<Subscriptions>());
+        }
+        for (section, keys) in section_keys() {
+            if let Some(table) = root.get(section).and_then(Item::as_table_like) {
+                for (key, _) in table.iter() {
+                    if !keys.contains(&key) {
+                        let offset = table
+                            .key(key)
+                            .and_then(|key| key.span())
+                            .map(|span| span.start);
+                        self.unknown_key(offset, format!("{}.{}", section, key));
+                    }
+                }
+            }
+        }
+
+        if let Some(defaults) = root.get("defaults").and_then(Item::as_table) {
+            for key in TEMPLATE_KEYS {
+                if let Some(template) = defaults.get(key).and_then(Item::as_str) {
+                    self.check_template(
+                        template,
+                        value_offset(defaults, key),
+                        format!("defaults.{}", key),
+                    );
+                    if key == "filename" {
+                        self.check_filename(
+                            template,
+                            value_offset(defaults, key),
+                            format!("defaults.{}", key),
+                        );
+                    }
+                }
+            }
+        }
+
+        let Some(subs) = root.get("sub") else {
+            return;
+        };
+        let Some(subs) = subs.as_array_of_tables() else {
+            self.push(
+                Severity::Error,
+                key_offset(root, "sub"),
+                Some("sub".to_string()),
+                "sub 必须是 [[sub]] 表数组".to_string(),
+            );
+            return;
+        };
+        for (index, sub) in subs.iter().enumerate() {
+            let field = format!("sub[{}]", index);
+            self.check_keys(sub, Some(&field), fields::<Subscription>());
+            if let Some(filter) = sub.get("filter").and_then(Item::as_table_like) {
+                for (key, _) in filter.iter() {
+                    if !fields::<ContentFilter>().contains(&key) {
+                        let offset = filter
+                            .key(key)
+                            .and_then(|key| key.span())
+                            .map(|span| span.start);
+                        self.unknown_key(offset, format!("{}.filter.{}", field, key));
+                    }
+                }
+            }
+
+            match sub.get("bvid").and_then(Item::as_str) {
+                None => self.push(
+                    Severity::Error,
+                    sub.span().map(|span| span.start),
+                    Some(field.clone()),
+                    "缺少 bvid".to_string(),
+                ),
+                Some(bvid) if utils::extract_bvid(bvid).as_deref() != Some(bvid) => self.push(
+                    Severity::Error,
+                    value_offset(sub, "bvid"),
+                    Some(format!("{}.bvid", field)),
+                    format!("无效的 BV 号: {}", bvid),
+                ),
+                Some(bvid) => {
+                    let output_dir = sub
+                        .get("output_dir")
+                        .and_then(Item::as_str)
+                        .map(str::to_string);
+                    if let Some(first) = self
+                        .seen
+                        .iter()
+                        .find(|seen| seen.bvid == bvid && seen.output_dir == output_dir)
+                    {
+                        let first = match first.file == self.path {
+                            true => format!("sub[{}]", first.index),
+                            false => format!("{} 中的 sub[{}]", first.file.display(), first.index),
+                        };
+                        self.push(
+                            Severity::Warning,
+                            value_offset(sub, "bvid"),
+                            Some(format!("{}.bvid", field)),
+                            format!("与 {} 重复订阅 {}", first, bvid),
+                        );
+                    } else {
+                        self.seen.push(SeenSubscription {
+                            bvid: bvid.to_string(),
+                            output_dir,
+                            file: self.path.to_path_buf(),
+                            index,
+                        });
+                    }
+                }
+            }
+
+            for key in TEMPLATE_KEYS {
+                if let Some(template) = sub.get(key).and_then(Item::as_str) {
+                    self.check_template(
+                        template,
+                        value_offset(sub, key),
+                        format!("{}.{}", field, key),
+                    );
+                    if key == "filename" {
+                        self.check_filename(
+                            template,
+                            value_offset(sub, key),
+                            format!("{}.{}", field, key),
+                        );
+                    }
+                }
+            }
+            self.check_extra_tags(sub, &field);
+            self.check_filter(sub, &field);
+        }
+    }
+
+    /// 额外标签只支持 [`EXTRA_TAG_KEYS`] 中的标签名，值为模板
+    fn check_extra_tags(&mut self, sub: &Table, field: &str) {
+        let Some(tags) = sub.get("extra_tags").and_then(Item::as_table_like) else {
+            return;
+        };
+        for (key, item) in tags.iter() {
+            let offset = tags
+                .key(key)
+                .and_then(|key| key.span())
+                .map(|span| span.start);
+            let field = format!("{}.extra_tags.{}", field, key);
+            if !EXTRA_TAG_KEYS.contains(&key) {
+                self.push(
+                    Severity::Warning,
+                    offset,
+                    Some(field.clone()),
+                    format!(
+                        "不支持的标签，将被忽略，可用的标签: {}",
+                        EXTRA_TAG_KEYS.join(" ")
+                    ),
+                );
+            }
+            if let Some(template) = item.as_str() {
+                self.check_template(template, offset, field);
+            }
+        }
+    }
+
+    /// 返回存在的被包含文件，不存在的文件报告为错误
+    fn check_includes(&mut self, root: &Table) -> Vec<PathBuf> {
+        let Some(include) = root.get("include").and_then(Item::as_array) else {
+            return Vec::new();
+        };
+        let mut includes = Vec::new();
+        for value in include.iter() {
+            let Some(file) = value.as_str() else {
+                continue;
+            };
+            let resolved = crate::config::include_path(self.path, Path::new(file));
+            if resolved.is_file() {
+                includes.push(resolved);
+            } else {
+                self.push(
+                    Severity::Error,
+                    value.span().map(|span| span.start),
+                    Some("include".to_string()),
+                    format!("被包含的订阅文件不存在: {}", resolved.display()),
+                );
+            }
+        }
+        includes
+    }
+
+    fn check_keys(&mut self, table: &Table, prefix: Option<&str>, known: &[&str]) {
+        for (key, _) in table.iter() {
+            if !known.contains(&key) {
+                let field = match prefix {
+                    Some(prefix) => format!("{}.{}", prefix, key),
+                    None => key.to_string(),
+                };
+                self.unknown_key(key_offset(table, key), field);
+            }
+        }
+    }
+
+    fn unknown_key(&mut self, offset: Option<usize>, field: String) {
+        self.push(
+            Severity::Warning,
+            offset,
+            Some(field),
+            "未知的配置项，将被忽略".to_string(),
+        );
+    }
+
+    /// 文件名模板中没有 `{page}` 时，多P视频会在文件名末尾追加分P编号
+    fn check_filename(&mut self, template: &str, offset: Option<usize>, field: String) {
+        if !template.contains("{page}") {
+            self.push(
+                Severity::Warning,
+                offset,
+                Some(field),
+                "文件名模板中没有 {page}，多P视频的文件名末尾会追加 -P<序号>".to_string(),
+            );
+        }
+    }
+
+    /// 模板中未知的通配符会原样保留在标签中
+    fn check_template(&mut self, template: &str, offset: Option<usize>, field: String) {
+        let wildcard = Regex::new(r"\{([^{}]*)\}").unwrap();
+        for name in wildcard
+            .captures_iter(template)
+            .map(|caps| caps[1].to_string())
+        {
+            if !WILDCARDS.contains(&name.as_str()) {
+                self.push(
+                    Severity::Warning,
+                    offset,
+                    Some(field.clone()),
+                    format!(
+                        "未知的通配符 {{{}}}，可用的通配符: {}",
+                        name,
+                        WILDCARDS
+                            .iter()
+                            .map(|name| format!("{{{}}}", name))
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    ),
+                );
+            }
+        }
+    }
+
+    fn check_filter(&mut self, sub: &Table, field: &str) {
+        let Some(filter) = sub.get("filter").and_then(Item::as_table_like) else {
+            return;
+        };
+        for key in [
+            "title_include",
+            "title_exclude",
+            "part_include",
+            "part_exclude",
+        ] {
+            let Some(pattern) = filter.get(key).and_then(Item::as_str) else {
+                continue;
+            };
+            let check = ContentFilter {
+                title_include: Some(pattern.to_string()),
+                ..Default::default()
+            };
+            if let Err(e) = check.compile() {
+                let offset = filter.get(key).and_then(Item::span).map(|span| span.start);
+                self.push(
+                    Severity::Error,
+                    offset,
+                    Some(format!("{}.filter.{}", field, key)),
+                    e.to_string(),
+                );
+            }
+        }
+    }
+}
+
+/// 加载错误中的字段路径，如 `sub[0].interval`
+fn error_key(error: &ConfigError) -> Option<String> {
+    let key = match error {
+        ConfigError::Type { key, .. } | ConfigError::At { key, .. } => key.as_deref()?,
+        _ => return None,
+    };
+    // config 生成的路径在下标后缺少点号，如 `sub[0]interval`
+    let mut normalized = String::new();
+    let mut chars = key.chars().peekable();
+    while let Some(c) = chars.next() {
+        normalized.push(c);
+        if c == ']' && chars.peek().is_some_and(|next| !matches!(next, '.' | '[')) {
+            normalized.push('.');
+        }
+    }
+    Some(normalized)
+}
+
+/// 按字段路径查找配置项在文件中的位置
+fn locate(root: &Table, key: &str) -> Option<usize> {
+    let mut table = root;
+    let mut offset = None;
+    let mut segments = key.split('.').peekable();
+    while let Some(segment) = segments.next() {
+        let (name, index) = match segment.split_once('[') {
+            Some((name, index)) => (name, index.trim_end_matches(']').parse::<usize>().ok()),
+            None => (segment, None),
+        };
+        let item = table.get(name)?;
+        offset = key_offset(table, name).or(offset);
+        let next = match (item, index) {
+            (Item::ArrayOfTables(tables), Some(index)) => {
+                let next = tables.get(index)?;
+                offset = next.span().map(|span| span.start).or(offset);
+                next
+            }
+            (Item::Table(next), None) => next,
+            _ => break,
+        };
+        if segments.peek().is_none() {
+            break;
+        }
+        table = next;
+    }
+    offset
+}
+
+fn key_offset(table: &Table, key: &str) -> Option<usize> {
+    table
+        .key(key)
+        .and_then(|key| key.span())
+        .map(|span| span.start)
+}
+
+fn value_offset(table: &Table, key: &str) -> Option<usize> {
+    table
+        .get(key)
+        .and_then(Item::span)
+        .map(|span| span.start)
+        .or_else(|| key_offset(table, key))
+}
+
+fn line_of(contents: &str, offset: usize) -> usize {
+    contents[..offset.min(contents.len())].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_lines_for_each_problem() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub.toml");
+        std::fs::write(
+            &path,
+            "[[sub]]\n\
+             bvid = \"BV1fk4y1x7Aa\"\n\
+             artsit = \"{artist}\"\n\
+             \n\
+             [[sub]]\n\
+             bvid = \"BV1fk4y1x7Aa\"\n\
+             title = \"{titel}\"\n\
+             \n\
+             [[sub]]\n\
+             bvid = \"av170001\"\n\
+             \n\
+             [[sub]]\n\
+             bvid = \"BV1nA4y1x7Ab\"\n\
+             interval = \"daily\"\n",
+        )
+        .unwrap();
+
+        let diagnostics = check_subscription_file(&path);
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.line, d.field.as_deref()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (Severity::Warning, Some(3), Some("sub[0].artsit")),
+                (Severity::Warning, Some(6), Some("sub[1].bvid")),
+                (Severity::Warning, Some(7), Some("sub[1].title")),
+                (Severity::Error, Some(10), Some("sub[2].bvid")),
+                (Severity::Error, Some(14), Some("sub[3].interval")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_same_video_in_different_output_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub.toml");
+        std::fs::write(
+            &path,
+            "[[sub]]\n\
+             bvid = \"BV1fk4y1x7Aa\"\n\
+             format = \"flac\"\n\
+             \n\
+             [[sub]]\n\
+             bvid = \"BV1fk4y1x7Aa\"\n\
+             output_dir = \"phone\"\n\
+             filename = \"{part_title}\"\n\
+             extra_tags = { genre = \"VOCALOID\", mood = \"{title}\" }\n",
+        )
+        .unwrap();
+
+        let diagnostics = check_subscription_file(&path);
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.line, d.field.as_deref()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (Severity::Warning, Some(8), Some("sub[1].filename")),
+                (Severity::Warning, Some(9), Some("sub[1].extra_tags.mood"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reports_syntax_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub.toml");
+        std::fs::write(&path, "[[sub]]\nbvid = \"BV1fk4y1x7Aa\n").unwrap();
+        let diagnostics = check_subscription_file(&path);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].line, Some(2));
+    }
+
+    #[test]
+    fn test_checks_included_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub.toml");
+        std::fs::write(
+            &path,
+            "include = [\"alice.toml\", \"missing.toml\"]\n\
+             \n\
+             [[sub]]\n\
+             bvid = \"BV1fk4y1x7Aa\"\n",
+        )
+        .unwrap();
+        let included = dir.path().join("alice.toml");
+        std::fs::write(
+            &included,
+            "[defaults]\n\
+             format = \"flac\"\n\
+             \n\
+             [[sub]]\n\
+             bvid = \"BV1nA4y1x7Ab\"\n\
+             tags = [\"vocaloid\"]\n\
+             titel = \"{title}\"\n\
+             \n\
+             [[sub]]\n\
+             bvid = \"BV1fk4y1x7Aa\"\n",
+        )
+        .unwrap();
+
+        let diagnostics = check_subscription_file(&path);
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.file.as_path(), d.severity, d.line, d.field.as_deref()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (path.as_path(), Severity::Error, Some(1), Some("include")),
+                (
+                    included.as_path(),
+                    Severity::Warning,
+                    Some(1),
+                    Some("defaults")
+                ),
+                (
+                    included.as_path(),
+                    Severity::Warning,
+                    Some(7),
+                    Some("sub[0].titel")
+                ),
+                (
+                    included.as_path(),
+                    Severity::Warning,
+                    Some(10),
+                    Some("sub[1].bvid")
+                ),
+            ]
+        );
+        let duplicate = diagnostics.last().unwrap();
+        assert!(
+            duplicate
+                .message
+                .contains(&format!("{} 中的 sub[0]", path.display()))
+        );
+    }
+
+    #[test]
+    fn test_known_keys_follow_serde() {
+        assert!(fields::<Subscription>().contains(&"on_removed"));
+        assert!(fields::<ContentFilter>().contains(&"min_views"));
+        assert!(fields::<ServerConfig>().contains(&"history"));
+        // 跳过反序列化的字段不是配置项
+        assert!(!fields::<Subscriptions>().contains(&"included_files"));
+        assert_eq!(fields::<IncludedSubscriptions>(), ["sub", "include"]);
+    }
+}
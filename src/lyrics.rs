@@ -0,0 +1,102 @@
+//! Lyrics taken from a part's CC subtitles
+//!
+//! Subtitles are listed by the player endpoint and stored as JSON on a static
+//! host. The chosen track is converted to LRC so it can be written next to the
+//! audio file and embedded into its tags.
+
+use log::debug;
+use serde::Deserialize;
+
+use crate::{api::endpoints, error::Result, user::User};
+
+/// 播放器接口返回的信息，只保留字幕部分
+#[derive(Debug, Default, Deserialize)]
+pub struct PlayerInfo {
+    #[serde(default)]
+    pub subtitle: SubtitleList,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SubtitleList {
+    #[serde(default)]
+    pub subtitles: Vec<SubtitleTrack>,
+}
+
+/// 一种语言的字幕
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubtitleTrack {
+    /// 语言代码，如 `zh-CN`、`ai-zh`
+    pub lan: String,
+    /// 字幕 JSON 地址，通常省略协议
+    pub subtitle_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubtitleBody {
+    body: Vec<SubtitleLine>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubtitleLine {
+    from: f64,
+    content: String,
+}
+
+/// 获取分P的字幕并转换为 LRC 歌词，没有字幕时返回 `None`
+///
+/// 优先选择中文字幕（包括 AI 生成的中文字幕），否则使用第一条字幕。
+pub async fn fetch(user: &User, bvid: &str, cid: i64) -> Result<Option<String>> {
+    let tracks = endpoints::get_subtitles(user, bvid, cid).await?;
+    let Some(track) = tracks
+        .iter()
+        .find(|track| track.lan.contains("zh"))
+        .or(tracks.first())
+    else {
+        debug!("分P {} 没有字幕", cid);
+        return Ok(None);
+    };
+    let url = match track.subtitle_url.strip_prefix("//") {
+        Some(url) => format!("https://{}", url),
+        None => track.subtitle_url.clone(),
+    };
+    debug!("使用 {} 字幕: {}", track.lan, url);
+    let body: SubtitleBody = serde_json::from_slice(&user.get_bytes(&url).await?)?;
+    Ok(Some(to_lrc(&body.body)))
+}
+
+/// 每行字幕转换为 `[mm:ss.xx]内容`
+fn to_lrc(lines: &[SubtitleLine]) -> String {
+    lines
+        .iter()
+        .map(|line| {
+            let centis = (line.from * 100.0).round() as u64;
+            format!(
+                "[{:02}:{:02}.{:02}]{}\n",
+                centis / 6000,
+                centis / 100 % 60,
+                centis % 100,
+                line.content.replace('\n', " ")
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_lrc() {
+        let lines = vec![
+            SubtitleLine {
+                from: 0.5,
+                content: "第一句".to_string(),
+            },
+            SubtitleLine {
+                from: 83.456,
+                content: "第二句\n换行".to_string(),
+            },
+        ];
+        assert_eq!(to_lrc(&lines), "[00:00.50]第一句\n[01:23.46]第二句 换行\n");
+    }
+}
@@ -1,106 +1,784 @@
-use chrono;
+use std::{path::Path, process::ExitCode, sync::LazyLock};
+
 use clap::Parser;
 use futures::future;
-use log::{debug, info, warn};
+use indicatif::MultiProgress;
+use indicatif_log_bridge::LogWrapper;
+use log::{debug, error, info, warn};
+use serde::Serialize;
 
 use ov_bilidown::{
     VideoBasicInfo,
-    config::{AppConfig, Cli},
-    error::Result,
-    subscription::Subscription,
+    api::endpoints,
+    archive::Archive,
+    config::{
+        AppConfig, ArchiveCommand, Cli, Command, Defaults, SelectArgs, SubsCommand, TemplateArgs,
+    },
+    converter::TrackTags,
+    daemon,
+    error::{BilidownError, Result},
+    lint::{self, Severity},
+    queue::JobQueue,
+    report::{
+        DownloadPlan, DownloadReport, OutputFormat, PlanAction, RemovedAction, RunSummary,
+        TrackStatus, VideoReport,
+    },
+    server,
+    subscription::{Subscription, SubscriptionEdit},
     user::User,
+    utils,
 };
 
+/// 全部进度条共用的绘制目标，输出日志与结果时会暂时隐藏进度条
+static BARS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+/// 全部成功
+const EXIT_SUCCESS: u8 = 0;
+/// 部分视频或分P失败
+const EXIT_PARTIAL_FAILURE: u8 = 1;
+/// 配置、登录等错误导致无法继续；2 留给 clap 的命令行参数错误
+const EXIT_FATAL: u8 = 3;
+
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
 
-    // 初始化日志系统
-    if cli.quiet {
+    // 初始化日志系统，日志输出时暂时隐藏进度条
+    let level = if cli.quiet {
         // 如果是安静模式，只显示错误
-        let _ = env_logger::Builder::new()
-            .filter_level(log::LevelFilter::Error)
-            .try_init();
+        log::LevelFilter::Error
     } else {
         // 根据详细程度设置日志级别
-        let level = match cli.verbose {
+        match cli.verbose {
             0 => log::LevelFilter::Info,
             1 => log::LevelFilter::Debug,
             2 => log::LevelFilter::Trace,
             _ => log::LevelFilter::Trace,
-        };
-
-        let _ = env_logger::Builder::new()
-            .filter_level(level)
-            .format(|buf, record| {
-                use std::io::Write;
-                writeln!(
-                    buf,
-                    "[{} {}] {}",
-                    chrono::Local::now().format("%H:%M:%S%.3f"),
-                    record.level(),
-                    record.args()
-                )
-            })
-            .try_init();
+        }
+    };
+    let logger = env_logger::Builder::new()
+        .filter_level(level)
+        .format(|buf, record| {
+            use std::io::Write;
+            writeln!(
+                buf,
+                "[{} {}] {}",
+                chrono::Local::now().format("%H:%M:%S%.3f"),
+                record.level(),
+                record.args()
+            )
+        })
+        .build();
+    if LogWrapper::new(BARS.clone(), logger).try_init().is_ok() {
+        log::set_max_level(level);
     }
 
     info!("应用启动");
 
-    let config = AppConfig::new(cli)?;
+    let quiet = cli.quiet;
+    let dry_run = cli.dry_run;
+    match run(cli).await {
+        Ok(None) => ExitCode::from(EXIT_SUCCESS),
+        Ok(Some(summary)) => {
+            if !quiet || summary.has_failures() {
+                BARS.suspend(|| print_summary(&summary, dry_run));
+            }
+            if summary.has_failures() {
+                ExitCode::from(EXIT_PARTIAL_FAILURE)
+            } else {
+                ExitCode::from(EXIT_SUCCESS)
+            }
+        }
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::from(EXIT_FATAL)
+        }
+    }
+}
+
+/// 执行子命令，下载与同步返回本次运行的汇总
+async fn run(cli: Cli) -> Result<Option<RunSummary>> {
+    let show_progress = !cli.quiet;
+    // 检查订阅文件时不能依赖订阅文件加载成功
+    if let Some(Command::Subs {
+        action: SubsCommand::Check,
+    }) = &cli.command
+    {
+        check_subscriptions(&cli.subscription_file(), cli.output)?;
+        return Ok(None);
+    }
+    let config = AppConfig::new(&cli)?;
     debug!(
         "配置已加载: output_dir={:?}, cookie_file={}",
         config.output_dir,
         config.cookie_file.to_string_lossy()
     );
 
-    let user = User::ensure_user(&config).await?;
+    let output = cli.output;
+    let dry_run = cli.dry_run;
+    match cli.command.unwrap_or(Command::Sync {
+        select: SelectArgs::default(),
+    }) {
+        Command::Login => {
+            User::login_and_save(&config).await?;
+        }
+        Command::Logout => {
+            if config.cookie_file.exists() {
+                std::fs::remove_file(&config.cookie_file)?;
+                println!("已退出登录，删除: {}", config.cookie_file.display());
+            } else {
+                println!("当前未登录");
+            }
+        }
+        Command::Whoami => {
+            let user = User::ensure_user(&config).await?;
+            let nav = endpoints::get_nav_info(&user).await?;
+            if nav.is_login {
+                println!(
+                    "{} (mid: {}){}",
+                    nav.uname.unwrap_or_default(),
+                    nav.mid.unwrap_or_default(),
+                    if nav.vip_status == Some(1) {
+                        " 大会员"
+                    } else {
+                        ""
+                    }
+                );
+            } else {
+                println!("当前未登录（游客模式）");
+            }
+        }
+        Command::Info { bvid } => {
+            let user = User::ensure_user(&config).await?;
+            let report = subscription(bvid, TemplateArgs::default(), &config.defaults)
+                .info(&user)
+                .await?;
+            emit(output, &report, print_info)?;
+        }
+        Command::Download { bvid, templates } => {
+            let mut summary = RunSummary::default();
+            let user = User::ensure_user(&config).await?;
+            let archive = Archive::load(&config.archive_file)?;
+            let queue = JobQueue::load(&config.queue_file)?;
+            let _attached = queue.attach(user.progress());
+            let subscription = subscription(bvid, templates, &config.defaults);
+            if dry_run {
+                let plan = subscription
+                    .plan(&user, &config.output_dir, Some(&archive))
+                    .await?;
+                emit(output, &plan, print_plan)?;
+                summary.add_plan(&plan);
+            } else {
+                let _bars = show_progress.then(|| user.progress().render_to(BARS.clone()));
+                let report = queue
+                    .download(&subscription, &user, &config.output_dir, Some(&archive))
+                    .await?;
+                emit(output, &report, print_download)?;
+                summary.add_download(&report);
+            }
+            return Ok(Some(summary));
+        }
+        Command::Sync { select } => {
+            return sync(&config, &select, output, dry_run, show_progress)
+                .await
+                .map(Some);
+        }
+        Command::Resume => {
+            return resume(&config, output, show_progress).await.map(Some);
+        }
+        Command::Daemon { interval } => {
+            let mut config = config;
+            if let Some(interval) = interval {
+                config.daemon.interval = interval;
+            }
+            let mut user = User::ensure_user(&config).await?;
+            let _bars = show_progress.then(|| user.progress().render_to(BARS.clone()));
+            daemon::run(&mut config, &mut user).await?;
+        }
+        Command::Serve { listen } => {
+            let user = User::ensure_user(&config).await?;
+            server::serve(&config, user, listen).await?;
+        }
+        Command::Subs { action } => subs(&config, action).await?,
+        Command::Archive { action } => archive(&config, action).await?,
+    }
+    Ok(None)
+}
 
-    match config.bvid {
-        Some(ref bvid) => {
-            let subscription = Subscription {
-                bvid: bvid.clone(),
-                title: Some("{title}".to_string()),
-                artist: None,
-                album: None,
-            };
-            subscription
-                .download(&user, &config.output_dir, config.info_only)
-                .await?
+/// 由命令行参数构造临时订阅并填入默认值，仍未指定歌曲名时使用视频标题
+fn subscription(bvid: String, templates: TemplateArgs, defaults: &Defaults) -> Subscription {
+    let mut subscription = Subscription {
+        bvid,
+        title: templates.title,
+        artist: templates.artist,
+        album: templates.album,
+        ..Default::default()
+    };
+    defaults.apply(&mut subscription);
+    subscription
+        .title
+        .get_or_insert_with(|| "{title}".to_string());
+    subscription
+}
+
+/// 按输出格式输出单个结果
+fn emit<T: Serialize>(output: OutputFormat, value: &T, print_text: fn(&T)) -> Result<()> {
+    BARS.suspend(|| match output {
+        OutputFormat::Text => {
+            print_text(value);
+            Ok(())
         }
-        None => {
-            let tasks = config
+        format => format.print(value),
+    })
+}
+
+async fn sync(
+    config: &AppConfig,
+    select: &SelectArgs,
+    output: OutputFormat,
+    dry_run: bool,
+    show_progress: bool,
+) -> Result<RunSummary> {
+    let mut summary = RunSummary::default();
+    let user = User::ensure_user(config).await?;
+    let _bars = (show_progress && !dry_run).then(|| user.progress().render_to(BARS.clone()));
+    let archive = Archive::load(&config.archive_file)?;
+    let queue = JobQueue::load(&config.queue_file)?;
+    let _attached = queue.attach(user.progress());
+    let output_dir = config.output_dir.as_path();
+    let leftover = queue
+        .unfinished()
+        .into_iter()
+        .filter(|video| {
+            !config
                 .subscriptions
                 .iter()
-                .enumerate()
-                .map(|(index, subscription)| {
-                    let user = &user;
-                    let output_dir = config.output_dir.as_path();
-                    let info_only = config.info_only;
-                    async move {
-                        let title = subscription.title.clone().unwrap_or_default();
-                        info!("开始处理订阅: {}:{}", index, title);
-                        match VideoBasicInfo::new_from_subscription(user, &subscription).await {
-                            Ok(videos) => {
-                                info!(
-                                    "订阅 {} 获取到视频: {} ({} - {})",
-                                    index, videos.title, videos.owner.name, videos.bvid
-                                );
-                                if let Err(e) =
-                                    subscription.download(&user, output_dir, info_only).await
-                                {
-                                    warn!("订阅 {}:{} 处理失败: {}", index, title, e);
-                                } else {
-                                    info!("订阅 {}:{} 处理完成", index, title);
-                                }
-                            }
-                            Err(e) => {
-                                warn!("订阅 {}:{} 获取视频失败: {}", index, title, e);
-                            }
+                .any(|subscription| subscription.key() == video.subscription.key())
+        })
+        .count();
+    if leftover > 0 {
+        warn!(
+            "有 {} 个上次中断的任务不在订阅文件中，可运行 resume 命令继续",
+            leftover
+        );
+    }
+    let subscriptions = selected(config, select);
+    if dry_run {
+        let plans = for_each(&subscriptions, output, print_plan, |index, subscription| {
+            let user = &user;
+            let archive = &archive;
+            async move {
+                match subscription.plan(user, output_dir, Some(archive)).await {
+                    Ok(plan) => plan,
+                    Err(e) => {
+                        warn!("订阅 {}:{} 获取视频失败: {}", index, subscription.bvid, e);
+                        DownloadPlan::failed(&subscription.bvid, e)
+                    }
+                }
+            }
+        })
+        .await?;
+        plans.iter().for_each(|plan| summary.add_plan(plan));
+    } else {
+        let reports = for_each(
+            &subscriptions,
+            output,
+            print_download,
+            |index, subscription| {
+                let user = &user;
+                let archive = &archive;
+                let queue = &queue;
+                async move {
+                    let title = subscription.title.clone().unwrap_or_default();
+                    info!("开始处理订阅: {}:{}", index, title);
+                    match queue
+                        .download(subscription, user, output_dir, Some(archive))
+                        .await
+                    {
+                        Ok(report) => {
+                            info!("订阅 {}:{} 处理完成", index, title);
+                            report
+                        }
+                        Err(e) => {
+                            warn!("订阅 {}:{} 处理失败: {}", index, title, e);
+                            DownloadReport::failed(&subscription.bvid, e)
                         }
                     }
-                });
-            future::join_all(tasks).await;
+                }
+            },
+        )
+        .await?;
+        reports
+            .iter()
+            .for_each(|report| summary.add_download(report));
+    }
+    Ok(summary)
+}
+
+/// 按 `--only`/`--skip` 选择订阅，没有匹配任何订阅的名称或标签会给出警告
+fn selected(config: &AppConfig, select: &SelectArgs) -> Vec<Subscription> {
+    for selector in select.only.iter().chain(&select.skip) {
+        if !config
+            .subscriptions
+            .iter()
+            .any(|subscription| subscription.matches(selector))
+        {
+            warn!("没有名称、标签或 BV 号为 {} 的订阅", selector);
+        }
+    }
+    let subscriptions: Vec<_> = config
+        .subscriptions
+        .iter()
+        .filter(|subscription| select.selects(subscription))
+        .cloned()
+        .collect();
+    if subscriptions.len() < config.subscriptions.len() {
+        info!(
+            "已选择 {} 个订阅，共 {} 个",
+            subscriptions.len(),
+            config.subscriptions.len()
+        );
+    }
+    subscriptions
+}
+
+/// 继续任务日志中未完成的任务，每个任务下载到其原来的输出目录
+async fn resume(
+    config: &AppConfig,
+    output: OutputFormat,
+    show_progress: bool,
+) -> Result<RunSummary> {
+    let mut summary = RunSummary::default();
+    let queue = JobQueue::load(&config.queue_file)?;
+    let unfinished = queue.unfinished();
+    if unfinished.is_empty() {
+        info!("没有需要继续的任务");
+        return Ok(summary);
+    }
+    info!("继续 {} 个上次中断的任务", unfinished.len());
+    let user = User::ensure_user(config).await?;
+    let _attached = queue.attach(user.progress());
+    let _bars = show_progress.then(|| user.progress().render_to(BARS.clone()));
+    let archive = Archive::load(&config.archive_file)?;
+    let reports = for_each(&unfinished, output, print_download, |_, video| {
+        let user = &user;
+        let archive = &archive;
+        let queue = &queue;
+        async move {
+            let subscription = &video.subscription;
+            match queue
+                .download(subscription, user, &video.output_dir, Some(archive))
+                .await
+            {
+                Ok(report) => report,
+                Err(e) => {
+                    warn!("任务 {} 处理失败: {}", subscription.bvid, e);
+                    DownloadReport::failed(&subscription.bvid, e)
+                }
+            }
+        }
+    })
+    .await?;
+    reports
+        .iter()
+        .for_each(|report| summary.add_download(report));
+    Ok(summary)
+}
+
+/// 并发处理全部任务并输出结果，NDJSON 在每个任务完成时立即输出一行
+async fn for_each<'a, I, T, F, Fut>(
+    items: &'a [I],
+    output: OutputFormat,
+    print_text: fn(&T),
+    run: F,
+) -> Result<Vec<T>>
+where
+    T: Serialize,
+    F: Fn(usize, &'a I) -> Fut,
+    Fut: Future<Output = T>,
+{
+    let tasks = items.iter().enumerate().map(|(index, subscription)| {
+        let task = run(index, subscription);
+        async move {
+            let result = task.await;
+            if output != OutputFormat::Json
+                && let Err(e) = emit(output, &result, print_text)
+            {
+                warn!("输出结果失败: {}", e);
+            }
+            result
+        }
+    });
+    let results = future::join_all(tasks).await;
+    if output == OutputFormat::Json {
+        BARS.suspend(|| output.print(&results))?;
+    }
+    Ok(results)
+}
+
+fn print_info(report: &VideoReport) {
+    let video = &report.video;
+    println!("视频信息:");
+    println!("标题: {}", video.title);
+    println!("UP主: {}", video.owner.name);
+    println!("播放数: {}", video.stat.view);
+    println!("时长: {}秒", video.duration);
+    println!("分P数: {}", report.parts.len());
+    for part in &report.parts {
+        println!("  - P{}: {} ({}秒)", part.page, part.part, part.duration);
+        if let Some(error) = &part.error {
+            println!("      获取音频流失败: {}", error);
+        } else if part.streams.is_empty() {
+            println!("      无可用音频流");
+        }
+        for stream in &part.streams {
+            let mut flags = Vec::new();
+            if stream.dolby {
+                flags.push("杜比");
+            }
+            if stream.hires {
+                flags.push("无损");
+            }
+            if stream.requires_vip {
+                flags.push("需大会员");
+            } else if stream.requires_login {
+                flags.push("需登录");
+            }
+            println!(
+                "      {:<16} {:<10} {:>5}kbps {}",
+                stream.quality.unwrap_or("未知质量"),
+                stream.codecs,
+                stream.bandwidth / 1024,
+                flags.join(" ")
+            );
+        }
+    }
+}
+
+fn print_plan(plan: &DownloadPlan) {
+    let name = plan.title.as_deref().unwrap_or(&plan.bvid);
+    if let Some(error) = &plan.error {
+        println!("{} 获取视频失败: {}", name, error);
+        return;
+    }
+    println!(
+        "{} ({}): 下载 {} 个，覆盖 {} 个，跳过 {} 个，不可用 {} 个",
+        name,
+        plan.bvid,
+        plan.count(PlanAction::Download),
+        plan.count(PlanAction::Overwrite),
+        plan.count(PlanAction::Skip),
+        plan.count(PlanAction::Unavailable),
+    );
+    for track in &plan.tracks {
+        let action = match track.action {
+            PlanAction::Download => "下载",
+            PlanAction::Overwrite => "覆盖",
+            PlanAction::Skip => "跳过",
+            PlanAction::Unavailable => "不可用",
+            PlanAction::Filtered => "已过滤",
+        };
+        println!("  [{}] P{} {}", action, track.page, track.part);
+        if let Some(tags) = &track.tags {
+            println!(
+                "      标题: {} / 艺术家: {} / 专辑: {}",
+                tags.title, tags.artist, tags.album
+            );
+        }
+        if let Some(quality) = &track.quality {
+            println!("      音质: {}", quality);
+        }
+        if let Some(path) = &track.path {
+            println!("      路径: {}", path.display());
+        }
+        if let Some(error) = &track.error {
+            println!("      错误: {}", error);
+        }
+    }
+}
+
+fn print_download(report: &DownloadReport) {
+    let name = report.title.as_deref().unwrap_or(&report.bvid);
+    if let Some(error) = &report.error {
+        println!("{} 下载失败: {}", name, error);
+        return;
+    }
+    if report.title.is_none() {
+        println!("{} 已失效", name);
+    } else {
+        println!(
+            "{} 下载完成! 新下载 {} 个，跳过 {} 个，失败 {} 个",
+            name,
+            report.count(TrackStatus::Downloaded),
+            report.count(TrackStatus::Skipped),
+            report.count(TrackStatus::Failed),
+        );
+    }
+    for track in report
+        .tracks
+        .iter()
+        .filter(|track| track.status == TrackStatus::Retagged)
+    {
+        println!(
+            "  - P{} {} 序号或标题已变化，已更新标签",
+            track.page, track.part
+        );
+    }
+    for track in &report.removed {
+        let action = match track.action {
+            RemovedAction::Kept => "已保留",
+            RemovedAction::Moved => "已移动",
+            RemovedAction::Deleted => "已删除",
+        };
+        match (&track.error, &track.moved_to) {
+            (Some(error), _) => println!(
+                "  - P{} {} 已从来源删除，处理失败: {}",
+                track.page, track.part, error
+            ),
+            (None, Some(target)) => println!(
+                "  - P{} {} 已从来源删除，{}到 {}",
+                track.page,
+                track.part,
+                action,
+                target.display()
+            ),
+            (None, None) => println!(
+                "  - P{} {} 已从来源删除，本地文件{}",
+                track.page, track.part, action
+            ),
+        }
+    }
+    for track in report.failed_tracks() {
+        println!(
+            "  - P{} {} 失败: {}",
+            track.page,
+            track.part,
+            track.error.as_deref().unwrap_or_default()
+        );
+    }
+    let limited: Vec<_> = report.limited_tracks().collect();
+    if !limited.is_empty() {
+        println!("以下 {} 个音轨因未登录仅获取到较低音质:", limited.len());
+        for track in limited {
+            println!(
+                "  - P{} {}: {}",
+                track.page,
+                track.part,
+                track.quality.as_deref().unwrap_or_default()
+            );
+        }
+    }
+}
+
+/// 输出到标准错误，不影响标准输出中的 JSON 结果
+fn print_summary(summary: &RunSummary, dry_run: bool) {
+    eprintln!(
+        "{}: {} {} 个，跳过 {} 个，不可用 {} 个，失败 {} 个",
+        if dry_run {
+            "预演汇总"
+        } else {
+            "运行汇总"
+        },
+        if dry_run { "将下载" } else { "新下载" },
+        summary.downloaded,
+        summary.skipped,
+        summary.unavailable,
+        summary.failures.len(),
+    );
+    if summary.removed > 0 {
+        eprintln!("来源中已删除，本地移动或删除 {} 个", summary.removed);
+    }
+    for failure in &summary.failures {
+        match failure.page {
+            Some(page) => eprintln!(
+                "  - {} P{} {}: {}",
+                failure.bvid,
+                page,
+                failure.part.as_deref().unwrap_or_default(),
+                failure.error
+            ),
+            None => eprintln!("  - {}: {}", failure.bvid, failure.error),
+        }
+    }
+}
+
+async fn subs(config: &AppConfig, action: SubsCommand) -> Result<()> {
+    let path = &config.subscription_file;
+    match action {
+        SubsCommand::List => {
+            println!("订阅文件: {}", path.display());
+            for file in &config.included_files {
+                println!("包含: {}", file.display());
+            }
+            for (index, sub) in config.subscriptions.iter().enumerate() {
+                let name = sub
+                    .name
+                    .as_ref()
+                    .map(|name| format!(" ({})", name))
+                    .unwrap_or_default();
+                let tags = match sub.tags.is_empty() {
+                    true => String::new(),
+                    false => format!(" [{}]", sub.tags.join(", ")),
+                };
+                println!(
+                    "{:>3}. {}{}{}  title={} artist={} album={}",
+                    index,
+                    sub.bvid,
+                    name,
+                    tags,
+                    sub.title.as_deref().unwrap_or("-"),
+                    sub.artist.as_deref().unwrap_or("-"),
+                    sub.album.as_deref().unwrap_or("-"),
+                );
+            }
+        }
+        SubsCommand::Add {
+            video,
+            templates,
+            interval,
+        } => {
+            let sub = Subscription {
+                bvid: parse_bvid(&video)?,
+                title: templates.title,
+                artist: templates.artist,
+                album: templates.album,
+                interval,
+                ..Default::default()
+            };
+            if config.subscriptions.iter().any(|s| s.key() == sub.key()) {
+                return Err(BilidownError::ArgumentError(format!(
+                    "{} 已在订阅文件中，可使用 subs set 修改",
+                    sub.bvid
+                )));
+            }
+            let user = User::ensure_user(config).await?;
+            let info = VideoBasicInfo::find_by_bvid(&user, &sub.bvid)
+                .await?
+                .ok_or_else(|| {
+                    BilidownError::ArgumentError(format!("视频 {} 不存在或已失效", sub.bvid))
+                })?;
+            sub.add_to_file(path)?;
+            println!(
+                "已添加订阅 {} ({} - {}) 到 {}",
+                sub.bvid,
+                info.title,
+                info.owner.name,
+                path.display()
+            );
+            // 以第一个分P展示模板渲染结果
+            if let Some(part) = info.pages.iter().flatten().next() {
+                let tags = TrackTags::render(&info, part, &sub);
+                println!(
+                    "  P{}: title={} artist={} album={}",
+                    part.page, tags.title, tags.artist, tags.album
+                );
+            }
+        }
+        SubsCommand::Remove { video } => {
+            let removed = Subscription::remove_from_file(path, &parse_bvid(&video)?)?;
+            println!("已删除 {} 条订阅", removed);
+        }
+        SubsCommand::Set {
+            video,
+            templates,
+            interval,
+            unset,
+        } => {
+            let bvid = parse_bvid(&video)?;
+            let edit = SubscriptionEdit {
+                title: templates.title,
+                artist: templates.artist,
+                album: templates.album,
+                interval,
+                unset,
+            };
+            match Subscription::edit_in_file(path, &bvid, &edit)? {
+                0 => {
+                    return Err(BilidownError::ArgumentError(format!(
+                        "订阅文件及其包含的文件中没有 {} 的订阅，可使用 subs add 添加",
+                        bvid
+                    )));
+                }
+                edited => println!("已修改 {} 条订阅", edited),
+            }
+        }
+        // 检查订阅文件不需要加载配置，已在 run 中处理
+        SubsCommand::Check => unreachable!("subs check 在加载配置之前处理"),
+    }
+    Ok(())
+}
+
+/// 从 BV 号或视频链接中取出 BV 号
+fn parse_bvid(video: &str) -> Result<String> {
+    utils::extract_bvid(video)
+        .ok_or_else(|| BilidownError::ArgumentError(format!("无法识别的视频: {}", video)))
+}
+
+/// 输出订阅文件的检查结果，存在错误时返回错误
+fn check_subscriptions(path: &Path, output: OutputFormat) -> Result<()> {
+    let diagnostics = lint::check_subscription_file(path);
+    match output {
+        OutputFormat::Text => {
+            for diagnostic in &diagnostics {
+                println!("{}: {}", diagnostic.file.display(), diagnostic);
+            }
+        }
+        OutputFormat::Json => output.print(&diagnostics)?,
+        OutputFormat::Ndjson => {
+            for diagnostic in &diagnostics {
+                output.print(diagnostic)?;
+            }
+        }
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    if output == OutputFormat::Text {
+        println!(
+            "检查完成: {} 个错误，{} 个警告",
+            errors,
+            diagnostics.len() - errors
+        );
+    }
+    if errors > 0 {
+        return Err(BilidownError::ValidationError(format!(
+            "订阅文件 {} 中有 {} 个错误",
+            path.display(),
+            errors
+        )));
+    }
+    Ok(())
+}
+
+async fn archive(config: &AppConfig, action: ArchiveCommand) -> Result<()> {
+    let archive = Archive::load(&config.archive_file)?;
+    match action {
+        ArchiveCommand::List { bvid } => {
+            println!("下载记录: {}", archive.path().display());
+            for entry in archive
+                .entries()
+                .into_iter()
+                .filter(|entry| bvid.as_ref().is_none_or(|bvid| &entry.bvid == bvid))
+            {
+                println!(
+                    "{} P{} {} - {} [{}] {}",
+                    entry.bvid,
+                    entry.page,
+                    entry.title,
+                    entry.part,
+                    entry.downloaded_at.format("%Y-%m-%d %H:%M"),
+                    entry.path.display()
+                );
+            }
+        }
+        ArchiveCommand::Remove { bvid } => {
+            let removed = archive.remove_bvid(&bvid).await?;
+            println!("已删除 {} 条下载记录", removed);
+        }
+        ArchiveCommand::Clear => {
+            let removed = archive.clear().await?;
+            println!("已清空 {} 条下载记录", removed);
         }
     }
     Ok(())
@@ -0,0 +1,143 @@
+//! Mirroring of parts that disappeared from a subscription's source
+//!
+//! A part is considered removed when the archive has an entry for it but the
+//! video no longer lists its cid, or when the whole video has been deleted.
+
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::{Archive, ArchiveEntry},
+    error::Result,
+    report::{RemovedAction, RemovedTrack},
+};
+
+/// 来源中的分P被删除后对本地文件的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemovedPolicy {
+    /// 保留文件，只在结果中列出
+    #[default]
+    Keep,
+    /// 移动到 `<输出目录>/removed/<bvid>`
+    Move,
+    /// 删除音频文件及同名的 `.lrc` 歌词文件
+    Delete,
+}
+
+impl RemovedPolicy {
+    pub fn is_keep(&self) -> bool {
+        *self == Self::Keep
+    }
+}
+
+/// 移动策略的目标目录
+pub fn removed_dir(output_dir: &Path, bvid: &str) -> PathBuf {
+    output_dir
+        .join("removed")
+        .join(crate::utils::sanitize_filename(bvid))
+}
+
+/// 按策略处理下载记录中已从来源消失的分P，移动或删除成功后移除其下载记录
+pub async fn apply(
+    archive: &Archive,
+    entries: Vec<ArchiveEntry>,
+    policy: RemovedPolicy,
+    removed_dir: &Path,
+) -> Vec<RemovedTrack> {
+    let mut tracks = Vec::new();
+    for entry in entries {
+        tracks.push(apply_entry(archive, entry, policy, removed_dir).await);
+    }
+    tracks
+}
+
+async fn apply_entry(
+    archive: &Archive,
+    entry: ArchiveEntry,
+    policy: RemovedPolicy,
+    removed_dir: &Path,
+) -> RemovedTrack {
+    let scope = entry.scope;
+    let mut track = RemovedTrack {
+        cid: entry.cid,
+        page: entry.page,
+        part: entry.part,
+        path: entry.path,
+        action: RemovedAction::Kept,
+        moved_to: None,
+        error: None,
+    };
+    // 开启歌词时写入的 `.lrc` 文件随音频文件一起处理
+    let lyrics = track.path.with_extension("lrc");
+    let result = match policy {
+        RemovedPolicy::Keep => {
+            info!(
+                "分P {} ({}) 已从来源删除，保留本地文件",
+                track.page, track.part
+            );
+            return track;
+        }
+        RemovedPolicy::Move => {
+            track.action = RemovedAction::Moved;
+            move_file(&track.path, removed_dir)
+                .map(|target| track.moved_to = target)
+                .and_then(|()| move_file(&lyrics, removed_dir).map(|_| ()))
+        }
+        RemovedPolicy::Delete => {
+            track.action = RemovedAction::Deleted;
+            delete_file(&track.path).and_then(|()| delete_file(&lyrics))
+        }
+    };
+    let result = match result {
+        Ok(()) => archive
+            .remove(track.cid, scope.as_deref())
+            .await
+            .map(|_| ()),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => info!(
+            "分P {} ({}) 已从来源删除，本地文件已{}",
+            track.page,
+            track.part,
+            if policy == RemovedPolicy::Move {
+                "移动"
+            } else {
+                "删除"
+            }
+        ),
+        Err(e) => {
+            warn!("处理已删除的分P {} 失败: {}", track.page, e);
+            track.error = Some(e.to_string());
+        }
+    }
+    track
+}
+
+/// 文件已不存在时视为已移动，返回 `None`
+fn move_file(path: &Path, dir: &Path) -> Result<Option<PathBuf>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let Some(name) = path.file_name() else {
+        return Ok(None);
+    };
+    std::fs::create_dir_all(dir)?;
+    let target = dir.join(name);
+    if std::fs::rename(path, &target).is_err() {
+        // 跨文件系统时无法直接重命名
+        std::fs::copy(path, &target)?;
+        std::fs::remove_file(path)?;
+    }
+    Ok(Some(target))
+}
+
+fn delete_file(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
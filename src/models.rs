@@ -11,6 +11,7 @@ pub struct VideoBasicInfo {
     pub title: String, // 视频标题
     pub desc: String,  // 视频简介
     pub duration: i32, // 稿件总时长(所有分P)
+    pub pubdate: i64,  // 发布时间(秒级时间戳)
 
     // 封面和分区信息
     pub pic: String,   // 封面图片url
@@ -0,0 +1,259 @@
+//! M3U8 and XSPF playlists of a subscription's downloaded tracks
+//!
+//! Playlists are rewritten after every download from the tracks whose files
+//! exist, so added and removed tracks are picked up automatically. Paths are
+//! relative to the playlist's directory.
+
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    VideoBasicInfo,
+    converter::TrackTags,
+    error::Result,
+    report::{TrackResult, TrackStatus},
+    subscription::Subscription,
+};
+
+/// 播放列表格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaylistFormat {
+    M3u8,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::M3u8 => "m3u8",
+            Self::Xspf => "xspf",
+        }
+    }
+}
+
+/// 播放列表中的一首歌
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistEntry {
+    pub path: PathBuf, // 相对于播放列表所在目录
+    pub tags: TrackTags,
+    pub duration: i32, // 秒
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Playlist {
+    pub title: String,
+    pub entries: Vec<PlaylistEntry>,
+}
+
+impl Playlist {
+    /// 按分P顺序收集输出文件存在的音轨
+    pub fn from_tracks(
+        video: &VideoBasicInfo,
+        subscription: &Subscription,
+        tracks: &[TrackResult],
+        dir: &Path,
+    ) -> Self {
+        let entries = video
+            .pages
+            .iter()
+            .flatten()
+            .filter_map(|part| {
+                let track = tracks.iter().find(|track| {
+                    track.cid == part.cid
+                        && matches!(
+                            track.status,
+                            TrackStatus::Downloaded | TrackStatus::Skipped | TrackStatus::Retagged
+                        )
+                })?;
+                let path = track.path.as_ref().filter(|path| path.exists())?;
+                Some(PlaylistEntry {
+                    path: relative_to(path, dir),
+                    tags: TrackTags::render(video, part, subscription),
+                    duration: part.duration,
+                })
+            })
+            .collect();
+        Self {
+            title: video.title.clone(),
+            entries,
+        }
+    }
+
+    /// 以视频标题为文件名写入 `dir`，返回写入的文件
+    pub fn write(&self, dir: &Path, formats: &[PlaylistFormat]) -> Result<Vec<PathBuf>> {
+        let name = crate::utils::sanitize_filename(&self.title);
+        formats
+            .iter()
+            .map(|format| {
+                let path = dir.join(format!("{}.{}", name, format.extension()));
+                let contents = match format {
+                    PlaylistFormat::M3u8 => self.to_m3u8(),
+                    PlaylistFormat::Xspf => self.to_xspf(),
+                };
+                std::fs::write(&path, contents)?;
+                Ok(path)
+            })
+            .collect()
+    }
+
+    pub fn to_m3u8(&self) -> String {
+        let mut out = format!("#EXTM3U\n#PLAYLIST:{}\n", self.title);
+        for entry in &self.entries {
+            out.push_str(&format!(
+                "#EXTINF:{},{} - {}\n{}\n",
+                entry.duration,
+                entry.tags.artist,
+                entry.tags.title,
+                entry.path.to_string_lossy()
+            ));
+        }
+        out
+    }
+
+    pub fn to_xspf(&self) -> String {
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+        );
+        out.push_str(&format!("  <title>{}</title>\n", escape_xml(&self.title)));
+        out.push_str("  <trackList>\n");
+        for entry in &self.entries {
+            out.push_str("    <track>\n");
+            out.push_str(&format!(
+                "      <location>{}</location>\n",
+                escape_xml(&encode_uri(&entry.path))
+            ));
+            out.push_str(&format!(
+                "      <title>{}</title>\n",
+                escape_xml(&entry.tags.title)
+            ));
+            out.push_str(&format!(
+                "      <creator>{}</creator>\n",
+                escape_xml(&entry.tags.artist)
+            ));
+            out.push_str(&format!(
+                "      <album>{}</album>\n",
+                escape_xml(&entry.tags.album)
+            ));
+            out.push_str(&format!(
+                "      <duration>{}</duration>\n",
+                i64::from(entry.duration) * 1000
+            ));
+            out.push_str("    </track>\n");
+        }
+        out.push_str("  </trackList>\n</playlist>\n");
+        out
+    }
+}
+
+/// 按订阅设置更新播放列表，失败只记录警告
+pub fn update(
+    video: &VideoBasicInfo,
+    subscription: &Subscription,
+    tracks: &[TrackResult],
+    dir: &Path,
+) {
+    if subscription.playlist.is_empty() {
+        return;
+    }
+    let playlist = Playlist::from_tracks(video, subscription, tracks, dir);
+    match playlist.write(dir, &subscription.playlist) {
+        Ok(paths) => {
+            for path in paths {
+                info!("播放列表已更新: {:?} ({} 首)", path, playlist.entries.len());
+            }
+        }
+        Err(e) => warn!("写入播放列表失败: {}", e),
+    }
+}
+
+/// 位于 `dir` 下的路径改为相对路径，其余保持不变
+fn relative_to(path: &Path, dir: &Path) -> PathBuf {
+    path.strip_prefix(dir)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// XSPF 的 location 是 URI，对路径中除 `/` 以外的保留字符进行百分号编码
+fn encode_uri(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let mut out = String::new();
+    if path.starts_with('/') {
+        out.push_str("file://");
+    }
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist() -> Playlist {
+        Playlist {
+            title: "测试合集".to_string(),
+            entries: vec![
+                PlaylistEntry {
+                    path: PathBuf::from("第一首 & 序曲.mp3"),
+                    tags: TrackTags {
+                        title: "第一首 & 序曲".to_string(),
+                        artist: "测试UP".to_string(),
+                        album: "测试合集".to_string(),
+                    },
+                    duration: 185,
+                },
+                PlaylistEntry {
+                    path: PathBuf::from("第二首.flac"),
+                    tags: TrackTags {
+                        title: "第二首".to_string(),
+                        artist: "测试UP".to_string(),
+                        album: "测试合集".to_string(),
+                    },
+                    duration: 60,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_m3u8_uses_relative_paths_and_durations() {
+        assert_eq!(
+            playlist().to_m3u8(),
+            "#EXTM3U\n#PLAYLIST:测试合集\n\
+             #EXTINF:185,测试UP - 第一首 & 序曲\n第一首 & 序曲.mp3\n\
+             #EXTINF:60,测试UP - 第二首\n第二首.flac\n"
+        );
+    }
+
+    #[test]
+    fn test_xspf_escapes_text_and_locations() {
+        let xspf = playlist().to_xspf();
+        assert!(xspf.contains("<title>第一首 &amp; 序曲</title>"));
+        assert!(xspf.contains(
+            "<location>%E7%AC%AC%E4%B8%80%E9%A6%96%20%26%20%E5%BA%8F%E6%9B%B2.mp3</location>"
+        ));
+        assert!(xspf.contains("<duration>185000</duration>"));
+        assert_eq!(
+            relative_to(Path::new("/a/b/c.mp3"), Path::new("/a")),
+            PathBuf::from("b/c.mp3")
+        );
+    }
+}
@@ -0,0 +1,388 @@
+//! Progress events emitted while downloading, and their terminal rendering
+//!
+//! Library users subscribe to [`Progress`] with a callback or a channel; the
+//! command line renders the same events as progress bars, or as periodic log
+//! lines when stderr is not a terminal.
+
+use std::{
+    collections::HashMap,
+    io::IsTerminal,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock, Weak},
+    time::{Duration, Instant},
+};
+
+use indicatif::{BinaryBytes, MultiProgress, ProgressBar, ProgressStyle};
+use log::info;
+use serde::Serialize;
+use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+
+use crate::{
+    converter::{AudioFormat, TrackTags},
+    report::TrackResult,
+};
+
+/// 非终端时同一音轨两条进度日志的最小间隔
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 解析视频与下载过程中的事件
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// 获取到视频信息
+    VideoResolved {
+        bvid: String,
+        title: String,
+        parts: usize,
+    },
+    /// 开始处理分P
+    TrackStarted {
+        bvid: String,
+        page: i32,
+        part: String,
+    },
+    /// 选定了要下载的音频流
+    StreamSelected {
+        bvid: String,
+        page: i32,
+        quality: String,
+        format: AudioFormat,
+        limited: bool, // 是否因未登录只获取到较低音质
+    },
+    /// 原始音频的下载进度，`total` 为服务器返回的文件大小
+    DownloadProgress {
+        bvid: String,
+        page: i32,
+        downloaded: u64,
+        total: Option<u64>,
+    },
+    /// 下载完成，开始转换格式
+    Converting { bvid: String, page: i32 },
+    /// 转换完成
+    Converted {
+        bvid: String,
+        page: i32,
+        path: PathBuf,
+    },
+    /// 元数据写入完成
+    Tagged {
+        bvid: String,
+        page: i32,
+        tags: TrackTags,
+    },
+    /// 分P下载、转换或校验失败
+    PartFailed {
+        bvid: String,
+        page: i32,
+        error: String,
+    },
+    /// 分P处理结束，附带该分P的处理结果
+    TrackFinished { bvid: String, track: TrackResult },
+}
+
+/// 视频 bvid 与分P序号
+type TrackKey = (String, i32);
+
+impl ProgressEvent {
+    fn key(&self) -> TrackKey {
+        match self {
+            Self::VideoResolved { bvid, .. } => (bvid.clone(), 0),
+            Self::TrackStarted { bvid, page, .. }
+            | Self::StreamSelected { bvid, page, .. }
+            | Self::DownloadProgress { bvid, page, .. }
+            | Self::Converting { bvid, page }
+            | Self::Converted { bvid, page, .. }
+            | Self::Tagged { bvid, page, .. }
+            | Self::PartFailed { bvid, page, .. } => (bvid.clone(), *page),
+            Self::TrackFinished { bvid, track } => (bvid.clone(), track.page),
+        }
+    }
+}
+
+/// 返回 `false` 表示监听器已失效，下次分发事件后移除
+type Listener = Box<dyn Fn(&ProgressEvent) -> bool + Send + Sync>;
+
+#[derive(Default)]
+struct Listeners {
+    next_id: u64,
+    entries: Vec<(u64, Listener)>,
+}
+
+/// 进度事件的分发器，克隆后共享同一组监听器
+#[derive(Clone, Default)]
+pub struct Progress {
+    listeners: Arc<RwLock<Listeners>>,
+}
+
+/// [`Progress::subscribe`] 注册的监听器，丢弃时注销
+///
+/// 需要在 `Progress` 的整个生命周期内监听时调用 [`ListenerGuard::detach`]。
+#[must_use = "丢弃后监听器会立即注销"]
+pub struct ListenerGuard {
+    listeners: Weak<RwLock<Listeners>>,
+    id: u64,
+}
+
+impl ListenerGuard {
+    /// 不再随本对象注销，监听器一直保留
+    pub fn detach(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for ListenerGuard {
+    fn drop(&mut self) {
+        if let Some(listeners) = self.listeners.upgrade() {
+            listeners
+                .write()
+                .unwrap()
+                .entries
+                .retain(|(id, _)| *id != self.id);
+        }
+    }
+}
+
+impl Progress {
+    /// 注册回调，每个事件都会在产生它的任务中同步调用，返回的对象被丢弃时注销
+    pub fn subscribe(
+        &self,
+        listener: impl Fn(&ProgressEvent) + Send + Sync + 'static,
+    ) -> ListenerGuard {
+        let id = self.register(Box::new(move |event| {
+            listener(event);
+            true
+        }));
+        ListenerGuard {
+            listeners: Arc::downgrade(&self.listeners),
+            id,
+        }
+    }
+
+    /// 以通道的形式接收之后的全部事件，接收端被丢弃后自动注销
+    pub fn channel(&self) -> UnboundedReceiver<ProgressEvent> {
+        let (tx, rx) = unbounded_channel();
+        self.register(Box::new(move |event| tx.send(event.clone()).is_ok()));
+        rx
+    }
+
+    fn register(&self, listener: Listener) -> u64 {
+        let mut listeners = self.listeners.write().unwrap();
+        let id = listeners.next_id;
+        listeners.next_id += 1;
+        listeners.entries.push((id, listener));
+        id
+    }
+
+    pub fn emit(&self, event: ProgressEvent) {
+        let closed: Vec<u64> = self
+            .listeners
+            .read()
+            .unwrap()
+            .entries
+            .iter()
+            .filter(|(_, listener)| !listener(&event))
+            .map(|(id, _)| *id)
+            .collect();
+        if !closed.is_empty() {
+            self.listeners
+                .write()
+                .unwrap()
+                .entries
+                .retain(|(id, _)| !closed.contains(id));
+        }
+    }
+
+    /// 监听器数量
+    pub fn listener_count(&self) -> usize {
+        self.listeners.read().unwrap().entries.len()
+    }
+
+    /// 在终端中以进度条显示进度，stderr 不是终端时定期输出日志
+    pub fn render_to(&self, multi: MultiProgress) -> ListenerGuard {
+        if std::io::stderr().is_terminal() {
+            let bars = ProgressBars::new(multi);
+            self.subscribe(move |event| bars.handle(event))
+        } else {
+            let log = ProgressLog::default();
+            self.subscribe(move |event| log.handle(event))
+        }
+    }
+}
+
+/// 每个正在下载的音轨一条进度条，底部为总进度
+struct ProgressBars {
+    multi: MultiProgress,
+    overall: ProgressBar,
+    parts: Mutex<HashMap<TrackKey, String>>, // 分P标题，用于进度条前缀
+    tracks: Mutex<HashMap<TrackKey, ProgressBar>>,
+}
+
+impl ProgressBars {
+    fn new(multi: MultiProgress) -> Self {
+        let overall = multi.add(
+            ProgressBar::new(0)
+                .with_style(
+                    ProgressStyle::with_template("{prefix:.bold} [{wide_bar}] {pos}/{len} 个音轨")
+                        .unwrap()
+                        .progress_chars("=> "),
+                )
+                .with_prefix("总进度"),
+        );
+        Self {
+            multi,
+            overall,
+            parts: Mutex::new(HashMap::new()),
+            tracks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn handle(&self, event: &ProgressEvent) {
+        let key = event.key();
+        match event {
+            ProgressEvent::TrackStarted { part, .. } => {
+                self.parts.lock().unwrap().insert(key, part.clone());
+                self.overall.inc_length(1);
+            }
+            ProgressEvent::DownloadProgress {
+                downloaded, total, ..
+            } => {
+                let bar = self.track_bar(key);
+                if let Some(total) = total {
+                    bar.set_length(*total);
+                }
+                bar.set_position(*downloaded);
+            }
+            ProgressEvent::Converting { .. } => {
+                self.track_bar(key).set_message("转换中");
+            }
+            ProgressEvent::TrackFinished { .. } => {
+                self.parts.lock().unwrap().remove(&key);
+                if let Some(bar) = self.tracks.lock().unwrap().remove(&key) {
+                    bar.finish_and_clear();
+                    self.multi.remove(&bar);
+                }
+                self.overall.inc(1);
+            }
+            _ => {}
+        }
+    }
+
+    /// 音轨第一次有进度时才创建进度条，跳过的音轨不会显示
+    fn track_bar(&self, key: TrackKey) -> ProgressBar {
+        let mut tracks = self.tracks.lock().unwrap();
+        if let Some(bar) = tracks.get(&key) {
+            return bar.clone();
+        }
+        let part = self.parts.lock().unwrap().get(&key).cloned();
+        let bar = self.multi.insert_before(
+            &self.overall,
+            ProgressBar::no_length()
+                .with_style(
+                    ProgressStyle::with_template(
+                        "{prefix:30!} [{wide_bar}] {binary_bytes}/{binary_total_bytes} {binary_bytes_per_sec} {eta} {msg}",
+                    )
+                    .unwrap()
+                    .progress_chars("=> "),
+                )
+                .with_prefix(format!("P{} {}", key.1, part.unwrap_or_default())),
+        );
+        tracks.insert(key, bar.clone());
+        bar
+    }
+}
+
+/// 非终端时的进度输出，每个音轨至多每 [`LOG_INTERVAL`] 输出一条日志
+#[derive(Default)]
+struct ProgressLog {
+    tracks: Mutex<HashMap<TrackKey, (String, Option<Instant>)>>, // 分P标题与上次输出时间
+    counts: Mutex<(usize, usize)>,                               // 已开始与已结束的音轨数
+}
+
+impl ProgressLog {
+    fn handle(&self, event: &ProgressEvent) {
+        let key = event.key();
+        match event {
+            ProgressEvent::TrackStarted { part, .. } => {
+                self.tracks
+                    .lock()
+                    .unwrap()
+                    .insert(key, (part.clone(), None));
+                self.counts.lock().unwrap().0 += 1;
+            }
+            ProgressEvent::DownloadProgress {
+                downloaded, total, ..
+            } => {
+                let mut tracks = self.tracks.lock().unwrap();
+                let Some((part, logged_at)) = tracks.get_mut(&key) else {
+                    return;
+                };
+                let done = *total == Some(*downloaded);
+                if !done && logged_at.is_some_and(|at| at.elapsed() < LOG_INTERVAL) {
+                    return;
+                }
+                *logged_at = Some(Instant::now());
+                match total {
+                    Some(total) if *total > 0 => info!(
+                        "P{} {} 已下载 {} / {} ({}%)",
+                        key.1,
+                        part,
+                        BinaryBytes(*downloaded),
+                        BinaryBytes(*total),
+                        downloaded * 100 / total
+                    ),
+                    _ => info!("P{} {} 已下载 {}", key.1, part, BinaryBytes(*downloaded)),
+                }
+            }
+            ProgressEvent::TrackFinished { .. } => {
+                self.tracks.lock().unwrap().remove(&key);
+                let mut counts = self.counts.lock().unwrap();
+                counts.1 += 1;
+                info!("总进度: {}/{} 个音轨", counts.1, counts.0);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_receives_emitted_events() {
+        let progress = Progress::default();
+        let mut rx = progress.channel();
+        let clone = progress.clone();
+        clone.emit(ProgressEvent::Converting {
+            bvid: "BV1fk4y1x7Aa".to_string(),
+            page: 2,
+        });
+        match rx.try_recv().unwrap() {
+            ProgressEvent::Converting { bvid, page } => {
+                assert_eq!(bvid, "BV1fk4y1x7Aa");
+                assert_eq!(page, 2);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_listeners_are_removed() {
+        let progress = Progress::default();
+        let guard = progress.subscribe(|_| {});
+        let rx = progress.channel();
+        progress.subscribe(|_| {}).detach();
+        assert_eq!(progress.listener_count(), 3);
+
+        drop(guard);
+        assert_eq!(progress.listener_count(), 2);
+        // 通道在接收端丢弃后的下一个事件时注销
+        drop(rx);
+        progress.emit(ProgressEvent::Converting {
+            bvid: "BV1fk4y1x7Aa".to_string(),
+            page: 1,
+        });
+        assert_eq!(progress.listener_count(), 1);
+    }
+}
@@ -0,0 +1,282 @@
+//! Journal of in-flight downloads so an interrupted run can resume
+//!
+//! Each video is recorded when its download starts and removed when it
+//! finishes, so whatever is left in the file after a crash or a kill is the
+//! work that still has to be done. Track states are updated from progress
+//! events.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use chrono::{DateTime, Local};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::Archive,
+    error::Result,
+    progress::{ListenerGuard, Progress, ProgressEvent},
+    report::{DownloadReport, TrackStatus},
+    subscription::Subscription,
+    user::User,
+    utils,
+};
+
+/// 分P的处理状态，`status` 为空表示已开始但尚未结束
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrackJob {
+    pub part: String,
+    pub status: Option<TrackStatus>,
+}
+
+/// 尚未完成的视频下载任务
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedVideo {
+    pub subscription: Subscription,
+    pub output_dir: PathBuf,
+    pub queued_at: DateTime<Local>,
+    pub tracks: BTreeMap<i32, TrackJob>,
+}
+
+/// 以订阅的 [`Subscription::key`] 为键的任务日志，每次修改后立即保存到文件
+pub struct JobQueue {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<String, QueuedVideo>>,
+    version: AtomicU64,       // 每次修改后递增
+    written: Arc<Mutex<u64>>, // 已写入文件的版本，较早的快照不会覆盖较新的
+}
+
+impl JobQueue {
+    /// 读取任务日志，文件不存在时返回空日志
+    ///
+    /// 文件无法解析时移动到 `<文件名>.corrupt` 并从空日志开始
+    pub fn load(path: &Path) -> Result<Arc<Self>> {
+        let entries = match std::fs::read_to_string(path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    let moved = utils::move_aside(path)?;
+                    warn!(
+                        "任务日志 {:?} 无法解析: {}，已移动到 {:?}，将从空日志开始",
+                        path, e, moved
+                    );
+                    BTreeMap::new()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Arc::new(Self {
+            path: path.to_path_buf(),
+            entries: Mutex::new(entries),
+            version: AtomicU64::new(0),
+            written: Arc::new(Mutex::new(0)),
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 根据进度事件更新分P状态，返回的对象被丢弃时停止更新
+    pub fn attach(self: &Arc<Self>, progress: &Progress) -> ListenerGuard {
+        let queue = Arc::downgrade(self);
+        progress.subscribe(move |event| {
+            if let Some(queue) = queue.upgrade() {
+                queue.on_progress(event);
+            }
+        })
+    }
+
+    /// 上次运行中断时未完成的任务
+    pub fn unfinished(&self) -> Vec<QueuedVideo> {
+        self.entries.lock().unwrap().values().cloned().collect()
+    }
+
+    /// 记录任务后下载，下载流程结束（无论成功与否）后移除记录
+    pub async fn download(
+        &self,
+        subscription: &Subscription,
+        user: &User,
+        output_dir: &Path,
+        archive: Option<&Archive>,
+    ) -> Result<DownloadReport> {
+        self.enqueue(subscription, output_dir);
+        let result = subscription.download(user, output_dir, archive).await;
+        self.finish(&subscription.key());
+        self.flush().await;
+        result
+    }
+
+    pub fn enqueue(&self, subscription: &Subscription, output_dir: &Path) {
+        self.modify(|entries| {
+            entries
+                .entry(subscription.key())
+                .or_insert_with(|| QueuedVideo {
+                    subscription: subscription.clone(),
+                    output_dir: output_dir.to_path_buf(),
+                    queued_at: Local::now(),
+                    tracks: BTreeMap::new(),
+                });
+        });
+    }
+
+    pub fn finish(&self, key: &str) {
+        self.modify(|entries| {
+            entries.remove(key);
+        });
+    }
+
+    fn on_progress(&self, event: &ProgressEvent) {
+        let (bvid, page, job) = match event {
+            ProgressEvent::TrackStarted { bvid, page, part } => (
+                bvid,
+                *page,
+                TrackJob {
+                    part: part.clone(),
+                    status: None,
+                },
+            ),
+            ProgressEvent::TrackFinished { bvid, track } => (
+                bvid,
+                track.page,
+                TrackJob {
+                    part: track.part.clone(),
+                    status: Some(track.status),
+                },
+            ),
+            _ => return,
+        };
+        self.modify(|entries| {
+            // 同一视频订阅到不同输出目录时，进度事件无法区分，一并更新
+            for video in entries
+                .values_mut()
+                .filter(|video| &video.subscription.bvid == bvid)
+            {
+                video.tracks.insert(page, job.clone());
+            }
+        });
+    }
+
+    /// 修改后在阻塞线程中保存，保存失败只记录警告，不影响下载
+    ///
+    /// 进度回调是同步调用的，不能在其中等待写入完成；不在异步运行时中时直接写入。
+    fn modify(&self, f: impl FnOnce(&mut BTreeMap<String, QueuedVideo>)) {
+        let save = {
+            let mut entries = self.entries.lock().unwrap();
+            f(&mut entries);
+            self.version.fetch_add(1, Ordering::SeqCst);
+            self.snapshot(&entries)
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(save)),
+            Err(_) => save(),
+        }
+    }
+
+    /// 等待此前的修改全部写入文件
+    pub async fn flush(&self) {
+        let save = self.snapshot(&self.entries.lock().unwrap());
+        if let Err(e) = tokio::task::spawn_blocking(save).await {
+            warn!("保存任务日志失败: {}", e);
+        }
+    }
+
+    /// 当前内容的写入操作，先写临时文件再替换，已写入更新的版本时跳过
+    fn snapshot(&self, entries: &BTreeMap<String, QueuedVideo>) -> impl FnOnce() + Send + 'static {
+        let version = self.version.load(Ordering::SeqCst);
+        let contents = serde_json::to_string_pretty(entries);
+        let (path, written) = (self.path.clone(), self.written.clone());
+        move || {
+            let mut written = written.lock().unwrap();
+            if version <= *written {
+                return;
+            }
+            match contents
+                .map_err(Into::into)
+                .and_then(|contents| utils::write_file_atomic(&path, contents.as_bytes()))
+            {
+                Ok(()) => *written = version,
+                Err(e) => warn!("保存任务日志失败: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::VideoPart, report::TrackResult};
+
+    #[test]
+    fn test_unfinished_jobs_survive_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.json");
+        let queue = JobQueue::load(&path).unwrap();
+        let progress = Progress::default();
+        let _attached = queue.attach(&progress);
+
+        let subscription = Subscription {
+            bvid: "BV1fk4y1x7Aa".to_string(),
+            artist: Some("{artist}".to_string()),
+            ..Default::default()
+        };
+        queue.enqueue(&subscription, dir.path());
+        queue.enqueue(
+            &Subscription {
+                bvid: "BV1nA4y1x7Ab".to_string(),
+                ..Default::default()
+            },
+            dir.path(),
+        );
+        progress.emit(ProgressEvent::TrackStarted {
+            bvid: "BV1fk4y1x7Aa".to_string(),
+            page: 1,
+            part: "第一首".to_string(),
+        });
+        let part: VideoPart = serde_json::from_value(serde_json::json!({
+            "cid": 1002, "page": 2, "from": "vupload", "part": "第二首", "duration": 60,
+            "dimension": { "width": 0, "height": 0, "rotate": 0 }
+        }))
+        .unwrap();
+        progress.emit(ProgressEvent::TrackFinished {
+            bvid: "BV1fk4y1x7Aa".to_string(),
+            track: TrackResult::new(&part, TrackStatus::Downloaded),
+        });
+        queue.finish("BV1nA4y1x7Ab");
+
+        let reloaded = JobQueue::load(&path).unwrap();
+        let unfinished = reloaded.unfinished();
+        assert_eq!(unfinished.len(), 1);
+        let video = &unfinished[0];
+        assert_eq!(video.subscription.artist.as_deref(), Some("{artist}"));
+        assert_eq!(video.tracks[&1].status, None);
+        assert_eq!(video.tracks[&2].status, Some(TrackStatus::Downloaded));
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_journal_is_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.json");
+        std::fs::write(&path, "{\"BV1fk4y1x7Aa\": ").unwrap();
+
+        let queue = JobQueue::load(&path).unwrap();
+        assert!(queue.unfinished().is_empty());
+        assert!(dir.path().join("queue.json.corrupt").exists());
+
+        let subscription = Subscription {
+            bvid: "BV1fk4y1x7Aa".to_string(),
+            ..Default::default()
+        };
+        queue.enqueue(&subscription, dir.path());
+        queue.flush().await;
+        assert_eq!(JobQueue::load(&path).unwrap().unfinished().len(), 1);
+        assert!(!dir.path().join("queue.json.tmp").exists());
+    }
+}
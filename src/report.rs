@@ -0,0 +1,473 @@
+//! Structured results of the info, download and dry-run commands
+//!
+//! These types are what `--output json` / `--output ndjson` serialize, and
+//! are also returned to library users instead of free text.
+
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    converter::{AudioFormat, TrackTags},
+    download::{AudioQuality, DashAudioStream},
+    error::Result,
+    models::{VideoBasicInfo, VideoPart},
+};
+
+/// 命令结果的输出格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// 便于阅读的文本
+    #[default]
+    Text,
+    /// 单个 JSON 文档，多个结果时为数组
+    Json,
+    /// 每行一个 JSON 对象
+    Ndjson,
+}
+
+impl OutputFormat {
+    /// 以 JSON 或 NDJSON 输出单个结果到标准输出
+    pub fn print<T: Serialize>(&self, value: &T) -> Result<()> {
+        match self {
+            Self::Text => {}
+            Self::Json => println!("{}", serde_json::to_string_pretty(value)?),
+            Self::Ndjson => println!("{}", serde_json::to_string(value)?),
+        }
+        Ok(())
+    }
+}
+
+/// 音频流概要
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamInfo {
+    pub id: u32,
+    pub quality: Option<&'static str>, // 音质名称，未知音质为 null
+    pub description: String,
+    pub bandwidth: u64,
+    pub mime_type: String,
+    pub codecs: String,
+    pub dolby: bool,
+    pub hires: bool,
+    pub requires_login: bool,
+    pub requires_vip: bool,
+}
+
+impl From<&DashAudioStream> for StreamInfo {
+    fn from(stream: &DashAudioStream) -> Self {
+        let quality = stream.get_quality();
+        Self {
+            id: stream.id,
+            quality: quality.map(|q| q.quality_name()),
+            description: stream.get_quality_description(),
+            bandwidth: stream.bandwidth,
+            mime_type: stream.mime_type.clone(),
+            codecs: stream.codecs.clone(),
+            dolby: quality == Some(AudioQuality::DolbyAtmos),
+            hires: quality == Some(AudioQuality::HiRes),
+            requires_login: quality.is_some_and(|q| q.requires_login()),
+            requires_vip: quality.is_some_and(|q| q.requires_vip()),
+        }
+    }
+}
+
+/// 分P及其可用的音频流
+#[derive(Debug, Serialize)]
+pub struct PartStreams {
+    pub cid: i64,
+    pub page: i32,
+    pub part: String,
+    pub duration: i32,
+    pub streams: Vec<StreamInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // 获取音频流失败的原因
+}
+
+impl PartStreams {
+    pub fn new(part: &VideoPart, streams: Result<Vec<DashAudioStream>>) -> Self {
+        let (streams, error) = match streams {
+            Ok(streams) => (streams.iter().map(StreamInfo::from).collect(), None),
+            Err(e) => (Vec::new(), Some(e.to_string())),
+        };
+        Self {
+            cid: part.cid,
+            page: part.page,
+            part: part.part.clone(),
+            duration: part.duration,
+            streams,
+            error,
+        }
+    }
+}
+
+/// `info` 命令的结果
+#[derive(Debug, Serialize)]
+pub struct VideoReport {
+    pub video: VideoBasicInfo,
+    pub parts: Vec<PartStreams>,
+}
+
+/// 单个音轨的处理状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackStatus {
+    /// 下载并转换完成
+    Downloaded,
+    /// 已在下载记录中，跳过
+    Skipped,
+    /// 已在下载记录中，分P序号或标题变化后重命名文件并重写了标签
+    Retagged,
+    /// 被订阅的过滤条件排除
+    Filtered,
+    /// 没有可用的音频流
+    Unavailable,
+    /// 下载、转换或校验失败
+    Failed,
+}
+
+/// 单个分P的下载结果
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackResult {
+    pub cid: i64,
+    pub page: i32,
+    pub part: String,
+    pub status: TrackStatus,
+    pub path: Option<PathBuf>,
+    pub format: Option<AudioFormat>,
+    pub quality: Option<String>,
+    pub size: Option<u64>, // 输出文件字节数
+    pub limited: bool,     // 是否因未登录只获取到较低音质
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TrackResult {
+    pub fn new(part: &VideoPart, status: TrackStatus) -> Self {
+        Self {
+            cid: part.cid,
+            page: part.page,
+            part: part.part.clone(),
+            status,
+            path: None,
+            format: None,
+            quality: None,
+            size: None,
+            limited: false,
+            error: None,
+        }
+    }
+
+    pub fn failed(mut self, error: impl ToString) -> Self {
+        self.status = TrackStatus::Failed;
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+/// 对来源中已不存在的分P执行的操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovedAction {
+    /// 保留本地文件与下载记录
+    Kept,
+    /// 移动到 `removed` 目录
+    Moved,
+    /// 已删除音频文件及同名的 `.lrc` 歌词文件
+    Deleted,
+}
+
+/// 来源中已删除的分P或已失效视频的分P
+#[derive(Debug, Clone, Serialize)]
+pub struct RemovedTrack {
+    pub cid: i64,
+    pub page: i32,
+    pub part: String,
+    pub path: PathBuf, // 原输出文件路径
+    pub action: RemovedAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moved_to: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // 移动或删除失败的原因，此时保留下载记录
+}
+
+/// 单个视频（订阅）的下载结果
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadReport {
+    pub bvid: String,
+    pub title: Option<String>, // 获取视频信息失败或视频已失效时为 null
+    pub tracks: Vec<TrackResult>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<RemovedTrack>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DownloadReport {
+    /// 获取视频信息失败时的结果
+    pub fn failed(bvid: &str, error: impl ToString) -> Self {
+        Self {
+            bvid: bvid.to_string(),
+            title: None,
+            tracks: Vec::new(),
+            removed: Vec::new(),
+            error: Some(error.to_string()),
+        }
+    }
+
+    /// 下载、转换或校验失败的音轨
+    pub fn failed_tracks(&self) -> impl Iterator<Item = &TrackResult> {
+        self.tracks
+            .iter()
+            .filter(|track| track.status == TrackStatus::Failed)
+    }
+
+    /// 获取视频信息失败或有音轨失败
+    pub fn has_failures(&self) -> bool {
+        self.error.is_some() || self.failed_tracks().next().is_some()
+    }
+
+    /// 因未登录仅获取到较低音质的音轨
+    pub fn limited_tracks(&self) -> impl Iterator<Item = &TrackResult> {
+        self.tracks.iter().filter(|track| track.limited)
+    }
+
+    pub fn count(&self, status: TrackStatus) -> usize {
+        self.tracks
+            .iter()
+            .filter(|track| track.status == status)
+            .count()
+    }
+}
+
+/// 预演时对单个分P的计划操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanAction {
+    /// 将下载到新文件
+    Download,
+    /// 将下载并覆盖已存在的文件
+    Overwrite,
+    /// 已在下载记录中，将跳过
+    Skip,
+    /// 没有可用的音频流
+    Unavailable,
+    /// 被订阅的过滤条件排除
+    Filtered,
+}
+
+/// 单个分P的预演结果
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedTrack {
+    pub cid: i64,
+    pub page: i32,
+    pub part: String,
+    pub action: PlanAction,
+    pub path: Option<PathBuf>,
+    pub format: Option<AudioFormat>,
+    pub quality: Option<String>,
+    pub tags: Option<TrackTags>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // 获取音频流失败的原因
+}
+
+impl PlannedTrack {
+    pub fn new(part: &VideoPart, action: PlanAction) -> Self {
+        Self {
+            cid: part.cid,
+            page: part.page,
+            part: part.part.clone(),
+            action,
+            path: None,
+            format: None,
+            quality: None,
+            tags: None,
+            error: None,
+        }
+    }
+}
+
+/// 单个视频（订阅）的预演结果，不会下载任何音频
+#[derive(Debug, Serialize)]
+pub struct DownloadPlan {
+    pub bvid: String,
+    pub title: Option<String>, // 获取视频信息失败时为 null
+    pub tracks: Vec<PlannedTrack>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DownloadPlan {
+    /// 获取视频信息失败时的结果
+    pub fn failed(bvid: &str, error: impl ToString) -> Self {
+        Self {
+            bvid: bvid.to_string(),
+            title: None,
+            tracks: Vec::new(),
+            error: Some(error.to_string()),
+        }
+    }
+
+    pub fn count(&self, action: PlanAction) -> usize {
+        self.tracks
+            .iter()
+            .filter(|track| track.action == action)
+            .count()
+    }
+}
+
+/// 失败的视频或分P
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Failure {
+    pub bvid: String,
+    pub page: Option<i32>,    // 获取视频信息失败时为 null
+    pub part: Option<String>, // 分P标题
+    pub error: String,
+}
+
+/// 一次运行中全部视频的汇总，预演时 `downloaded` 为将要下载（含覆盖）的分P数
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub downloaded: usize,
+    pub skipped: usize,
+    pub unavailable: usize,
+    #[serde(default)]
+    pub removed: usize, // 因来源删除而移动或删除的本地文件数
+    pub failures: Vec<Failure>,
+}
+
+impl RunSummary {
+    pub fn add_download(&mut self, report: &DownloadReport) {
+        if let Some(error) = &report.error {
+            self.add_failure(&report.bvid, None, error);
+        }
+        for track in &report.tracks {
+            match track.status {
+                TrackStatus::Downloaded => self.downloaded += 1,
+                // 重写标签的分P没有重新下载
+                TrackStatus::Skipped | TrackStatus::Retagged | TrackStatus::Filtered => {
+                    self.skipped += 1
+                }
+                TrackStatus::Unavailable => self.unavailable += 1,
+                TrackStatus::Failed => self.add_failure(
+                    &report.bvid,
+                    Some((track.page, &track.part)),
+                    track.error.as_deref().unwrap_or_default(),
+                ),
+            }
+        }
+        for track in &report.removed {
+            match &track.error {
+                Some(error) => {
+                    self.add_failure(&report.bvid, Some((track.page, &track.part)), error)
+                }
+                None if track.action != RemovedAction::Kept => self.removed += 1,
+                None => {}
+            }
+        }
+    }
+
+    pub fn add_plan(&mut self, plan: &DownloadPlan) {
+        if let Some(error) = &plan.error {
+            self.add_failure(&plan.bvid, None, error);
+        }
+        for track in &plan.tracks {
+            match (track.action, &track.error) {
+                (_, Some(error)) => {
+                    self.add_failure(&plan.bvid, Some((track.page, &track.part)), error)
+                }
+                (PlanAction::Download | PlanAction::Overwrite, None) => self.downloaded += 1,
+                (PlanAction::Skip | PlanAction::Filtered, None) => self.skipped += 1,
+                (PlanAction::Unavailable, None) => self.unavailable += 1,
+            }
+        }
+    }
+
+    fn add_failure(&mut self, bvid: &str, part: Option<(i32, &String)>, error: &str) {
+        self.failures.push(Failure {
+            bvid: bvid.to_string(),
+            page: part.map(|(page, _)| page),
+            part: part.map(|(_, part)| part.clone()),
+            error: error.to_string(),
+        });
+    }
+
+    pub fn has_failures(&self) -> bool {
+        !self.failures.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(id: u32, mime_type: &str, codecs: &str) -> DashAudioStream {
+        DashAudioStream {
+            id,
+            base_url: String::new(),
+            backup_url: None,
+            bandwidth: 1024 * 1000,
+            mime_type: mime_type.to_string(),
+            codecs: codecs.to_string(),
+            segment_base: None,
+            codecid: 0,
+        }
+    }
+
+    #[test]
+    fn test_stream_info_flags() {
+        let flac = StreamInfo::from(&stream(30251, "audio/mp4", "fLaC"));
+        assert_eq!(flac.quality, Some("Hi-Res无损"));
+        assert!(flac.hires && !flac.dolby);
+        assert!(flac.requires_login && flac.requires_vip);
+
+        let dolby = StreamInfo::from(&stream(30250, "audio/mp4", "ec-3"));
+        assert!(dolby.dolby && dolby.requires_vip);
+
+        let standard = StreamInfo::from(&stream(30280, "audio/mp4", "mp4a.40.2"));
+        assert!(standard.requires_login && !standard.requires_vip);
+
+        let guest = StreamInfo::from(&stream(30232, "audio/mp4", "mp4a.40.2"));
+        assert!(!guest.requires_login);
+
+        let unknown = StreamInfo::from(&stream(12345, "audio/mp4", "mp4a.40.2"));
+        assert_eq!(unknown.quality, None);
+        assert!(!unknown.requires_login && !unknown.requires_vip);
+    }
+
+    #[test]
+    fn test_run_summary_counts_failures() {
+        let part = |page: i32| VideoPart {
+            cid: page as i64,
+            page,
+            from: "vupload".to_string(),
+            part: format!("P{}", page),
+            duration: 60,
+            dimension: crate::models::VideoDimension {
+                width: 0,
+                height: 0,
+                rotate: 0,
+            },
+        };
+        let mut summary = RunSummary::default();
+        summary.add_download(&DownloadReport {
+            bvid: "BV1fk4y1x7Aa".to_string(),
+            title: Some("测试合集".to_string()),
+            tracks: vec![
+                TrackResult::new(&part(1), TrackStatus::Downloaded),
+                TrackResult::new(&part(2), TrackStatus::Skipped),
+                TrackResult::new(&part(3), TrackStatus::Downloaded).failed("转换失败"),
+            ],
+            removed: Vec::new(),
+            error: None,
+        });
+        summary.add_download(&DownloadReport::failed("BV1xx411c7Xd", "稿件不可见"));
+
+        assert_eq!((summary.downloaded, summary.skipped), (1, 1));
+        assert!(summary.has_failures());
+        assert_eq!(summary.failures.len(), 2);
+        assert_eq!(summary.failures[0].page, Some(3));
+        assert_eq!(summary.failures[0].error, "转换失败");
+        assert_eq!(summary.failures[1].page, None);
+    }
+}
@@ -0,0 +1,516 @@
+//! Local HTTP/JSON API for queueing downloads
+//!
+//! Jobs run through the same pipeline as [`Subscription::download`]; their
+//! per-track progress is collected from the user's progress events.
+
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use axum::{
+    Json, Router,
+    extract::{Path, Request, State},
+    http::{StatusCode, header::AUTHORIZATION},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, sync::Semaphore, task::AbortHandle};
+
+use crate::{
+    archive::Archive,
+    config::{AppConfig, Defaults, Overrides, read_subscriptions},
+    error::Result,
+    progress::{ListenerGuard, ProgressEvent},
+    queue::JobQueue,
+    report::{DownloadReport, TrackStatus},
+    subscription::Subscription,
+    user::User,
+    utils,
+};
+
+/// 控制接口设置，对应配置文件中的 `[server]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// 监听地址，默认只监听本机
+    pub listen: SocketAddr,
+    /// 设置后所有请求必须携带 `Authorization: Bearer <token>`
+    pub token: Option<String>,
+    /// 同时运行的任务数
+    pub jobs: usize,
+    /// 保留的已结束任务数，超出时丢弃最早结束的任务
+    pub history: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([127, 0, 0, 1], 7878)),
+            token: None,
+            jobs: 2,
+            history: 100,
+        }
+    }
+}
+
+/// 任务状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// 等待运行
+    Queued,
+    Running,
+    /// 下载流程结束，个别分P可能失败，详见 `report`
+    Completed,
+    /// 获取视频信息等错误导致整个任务失败
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    fn is_finished(&self) -> bool {
+        matches!(self, Self::Completed | Self::Failed | Self::Cancelled)
+    }
+}
+
+/// 单个分P的实时进度
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TrackProgress {
+    pub part: String,
+    pub downloaded: u64,
+    pub total: Option<u64>,
+    pub status: Option<TrackStatus>, // 处理结束后的状态
+}
+
+/// 下载任务
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: u64,
+    pub subscription: Subscription,
+    pub status: JobStatus,
+    pub created_at: DateTime<Local>,
+    pub started_at: Option<DateTime<Local>>,
+    pub finished_at: Option<DateTime<Local>>,
+    pub parts: Option<usize>, // 获取视频信息后的分P数
+    pub tracks: BTreeMap<i32, TrackProgress>,
+    pub report: Option<DownloadReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// `POST /jobs` 的请求体
+#[derive(Debug, Deserialize)]
+pub struct EnqueueRequest {
+    /// BV号或视频链接
+    pub url: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+type ApiResult<T> = std::result::Result<T, (StatusCode, Json<ErrorBody>)>;
+
+fn api_error(status: StatusCode, error: impl ToString) -> (StatusCode, Json<ErrorBody>) {
+    (
+        status,
+        Json(ErrorBody {
+            error: error.to_string(),
+        }),
+    )
+}
+
+tokio::task_local! {
+    /// 正在运行的任务编号，用于将进度事件归属到产生它的任务
+    static JOB_ID: u64;
+}
+
+/// 任务队列与执行任务所需的会话
+pub struct Server {
+    user: User,
+    archive: Archive,
+    queue: Arc<JobQueue>,
+    output_dir: PathBuf,
+    subscription_file: PathBuf,
+    defaults: Defaults,
+    overrides: Overrides,
+    token: Option<String>,
+    history: usize,
+    slots: Semaphore,
+    next_id: AtomicU64,
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    handles: Mutex<BTreeMap<u64, AbortHandle>>,
+    _listeners: [ListenerGuard; 2], // 服务器释放时注销任务日志与进度的监听
+}
+
+impl Server {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        user: User,
+        archive: Archive,
+        queue: Arc<JobQueue>,
+        output_dir: PathBuf,
+        subscription_file: PathBuf,
+        defaults: Defaults,
+        overrides: Overrides,
+        config: &ServerConfig,
+    ) -> Arc<Self> {
+        Arc::new_cyclic(|weak: &Weak<Self>| {
+            let weak = weak.clone();
+            let listeners = [
+                queue.attach(user.progress()),
+                user.progress().subscribe(move |event| {
+                    if let Some(server) = weak.upgrade() {
+                        server.on_progress(event);
+                    }
+                }),
+            ];
+            Self {
+                user,
+                archive,
+                queue,
+                output_dir,
+                subscription_file,
+                defaults,
+                overrides,
+                token: config.token.clone(),
+                history: config.history,
+                slots: Semaphore::new(config.jobs.max(1)),
+                next_id: AtomicU64::new(1),
+                jobs: Mutex::new(BTreeMap::new()),
+                handles: Mutex::new(BTreeMap::new()),
+                _listeners: listeners,
+            }
+        })
+    }
+
+    pub fn router(self: &Arc<Self>) -> Router {
+        Router::new()
+            .route("/jobs", post(enqueue))
+            .route("/jobs/{id}", get(job).delete(cancel))
+            .route("/queue", get(queue))
+            .route("/history", get(history))
+            .route("/sync", post(sync))
+            .route_layer(middleware::from_fn_with_state(self.clone(), authorize))
+            .with_state(self.clone())
+    }
+
+    /// 将上次运行中断的任务重新加入队列，返回恢复的任务
+    ///
+    /// 只恢复输出目录与当前一致的任务，其余任务留给 `resume` 命令。
+    pub fn restore(self: &Arc<Self>) -> Vec<Job> {
+        self.queue
+            .unfinished()
+            .into_iter()
+            .filter(|video| video.output_dir == self.output_dir)
+            .map(|video| self.enqueue(video.subscription))
+            .collect()
+    }
+
+    /// 加入队列，同一订阅已在队列中时返回已有的任务
+    pub fn enqueue(self: &Arc<Self>, subscription: Subscription) -> Job {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs
+            .values()
+            .find(|job| !job.status.is_finished() && job.subscription.key() == subscription.key())
+        {
+            return job.clone();
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        info!("任务 {} 加入队列: {}", id, subscription.bvid);
+        let job = Job {
+            id,
+            subscription,
+            status: JobStatus::Queued,
+            created_at: Local::now(),
+            started_at: None,
+            finished_at: None,
+            parts: None,
+            tracks: BTreeMap::new(),
+            report: None,
+            error: None,
+        };
+        jobs.insert(id, job.clone());
+        drop(jobs);
+
+        // 持有锁直到记录下句柄，任务很快结束或被取消时才能找到它
+        let mut handles = self.handles.lock().unwrap();
+        let server = self.clone();
+        let handle = tokio::spawn(JOB_ID.scope(id, async move { server.run(id).await }));
+        handles.insert(id, handle.abort_handle());
+        job
+    }
+
+    async fn run(&self, id: u64) {
+        let _permit = self.slots.acquire().await.expect("semaphore closed");
+        let Some(subscription) = self
+            .update(id, |job| {
+                (job.status == JobStatus::Queued).then(|| {
+                    job.status = JobStatus::Running;
+                    job.started_at = Some(Local::now());
+                    job.subscription.clone()
+                })
+            })
+            .flatten()
+        else {
+            return;
+        };
+        let result = self
+            .queue
+            .download(
+                &subscription,
+                &self.user,
+                &self.output_dir,
+                Some(&self.archive),
+            )
+            .await;
+        self.update(id, |job| {
+            if job.status == JobStatus::Cancelled {
+                return;
+            }
+            job.finished_at = Some(Local::now());
+            match result {
+                Ok(report) => {
+                    job.status = JobStatus::Completed;
+                    job.report = Some(report);
+                }
+                Err(e) => {
+                    warn!("任务 {} 失败: {}", id, e);
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e.to_string());
+                }
+            }
+        });
+        self.handles.lock().unwrap().remove(&id);
+        self.prune_history();
+    }
+
+    /// 取消排队中或运行中的任务，已结束的任务返回 `None`
+    pub fn cancel(&self, id: u64) -> Option<Job> {
+        let job = self.update(id, |job| {
+            if job.status.is_finished() {
+                return None;
+            }
+            job.status = JobStatus::Cancelled;
+            job.finished_at = Some(Local::now());
+            Some(job.clone())
+        })??;
+        if let Some(handle) = self.handles.lock().unwrap().remove(&id) {
+            handle.abort();
+        }
+        self.queue.finish(&job.subscription.key());
+        info!("任务 {} 已取消", id);
+        self.prune_history();
+        Some(job)
+    }
+
+    /// 已结束的任务超过 `history` 时丢弃最早结束的任务
+    fn prune_history(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut finished: Vec<(DateTime<Local>, u64)> = jobs
+            .values()
+            .filter(|job| job.status.is_finished())
+            .map(|job| (job.finished_at.unwrap_or(job.created_at), job.id))
+            .collect();
+        if finished.len() <= self.history {
+            return;
+        }
+        finished.sort();
+        let excess = finished.len() - self.history;
+        for (_, id) in &finished[..excess] {
+            jobs.remove(id);
+        }
+    }
+
+    pub fn job(&self, id: u64) -> Option<Job> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    /// 按任务编号顺序列出满足条件的任务
+    pub fn jobs(&self, filter: impl Fn(&Job) -> bool) -> Vec<Job> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|job| filter(job))
+            .cloned()
+            .collect()
+    }
+
+    fn update<T>(&self, id: u64, f: impl FnOnce(&mut Job) -> T) -> Option<T> {
+        self.jobs.lock().unwrap().get_mut(&id).map(f)
+    }
+
+    /// 将进度事件记录到产生它的任务，不属于任何任务的事件被忽略
+    fn on_progress(&self, event: &ProgressEvent) {
+        let Ok(id) = JOB_ID.try_with(|id| *id) else {
+            return;
+        };
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs
+            .get_mut(&id)
+            .filter(|job| job.status == JobStatus::Running)
+        else {
+            return;
+        };
+        match event {
+            ProgressEvent::VideoResolved { parts, .. } => job.parts = Some(*parts),
+            ProgressEvent::TrackStarted { page, part, .. } => {
+                job.tracks.entry(*page).or_default().part = part.clone();
+            }
+            ProgressEvent::DownloadProgress {
+                page,
+                downloaded,
+                total,
+                ..
+            } => {
+                let track = job.tracks.entry(*page).or_default();
+                track.downloaded = *downloaded;
+                track.total = *total;
+            }
+            ProgressEvent::TrackFinished { track, .. } => {
+                job.tracks.entry(track.page).or_default().status = Some(track.status);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 配置了 token 时校验 `Authorization: Bearer <token>`
+async fn authorize(State(server): State<Arc<Server>>, request: Request, next: Next) -> Response {
+    if let Some(token) = &server.token {
+        let authorized = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|value| constant_time_eq(value.as_bytes(), token.as_bytes()));
+        if !authorized {
+            return api_error(StatusCode::UNAUTHORIZED, "缺少或错误的 token").into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// 比较耗时与首个不同字节的位置无关，避免通过响应时间猜测 token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+async fn enqueue(
+    State(server): State<Arc<Server>>,
+    Json(request): Json<EnqueueRequest>,
+) -> ApiResult<(StatusCode, Json<Job>)> {
+    let bvid = utils::extract_bvid(&request.url).ok_or_else(|| {
+        api_error(
+            StatusCode::BAD_REQUEST,
+            format!("无法识别的视频: {}", request.url),
+        )
+    })?;
+    let mut subscription = Subscription {
+        bvid,
+        title: request.title,
+        artist: request.artist,
+        album: request.album,
+        ..Default::default()
+    };
+    server.defaults.apply(&mut subscription);
+    server.overrides.apply(&mut subscription);
+    subscription
+        .title
+        .get_or_insert_with(|| "{title}".to_string());
+    let job = server.enqueue(subscription);
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+async fn job(State(server): State<Arc<Server>>, Path(id): Path<u64>) -> ApiResult<Json<Job>> {
+    server
+        .job(id)
+        .map(Json)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, format!("任务 {} 不存在", id)))
+}
+
+async fn cancel(State(server): State<Arc<Server>>, Path(id): Path<u64>) -> ApiResult<Json<Job>> {
+    if server.job(id).is_none() {
+        return Err(api_error(
+            StatusCode::NOT_FOUND,
+            format!("任务 {} 不存在", id),
+        ));
+    }
+    server
+        .cancel(id)
+        .map(Json)
+        .ok_or_else(|| api_error(StatusCode::CONFLICT, format!("任务 {} 已结束", id)))
+}
+
+/// 排队中与运行中的任务
+async fn queue(State(server): State<Arc<Server>>) -> Json<Vec<Job>> {
+    Json(server.jobs(|job| !job.status.is_finished()))
+}
+
+/// 已结束的任务
+async fn history(State(server): State<Arc<Server>>) -> Json<Vec<Job>> {
+    Json(server.jobs(|job| job.status.is_finished()))
+}
+
+/// 重新读取订阅文件，将全部订阅加入队列
+async fn sync(State(server): State<Arc<Server>>) -> ApiResult<(StatusCode, Json<Vec<Job>>)> {
+    let subscriptions = read_subscriptions(&server.subscription_file)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let jobs = subscriptions
+        .into_iter()
+        .map(|mut subscription| {
+            server.defaults.apply(&mut subscription);
+            server.overrides.apply(&mut subscription);
+            server.enqueue(subscription)
+        })
+        .collect();
+    Ok((StatusCode::ACCEPTED, Json(jobs)))
+}
+
+/// 启动控制接口，直到收到 Ctrl-C
+pub async fn serve(config: &AppConfig, user: User, listen: Option<SocketAddr>) -> Result<()> {
+    let archive = Archive::load(&config.archive_file)?;
+    let queue = JobQueue::load(&config.queue_file)?;
+    let server = Server::new(
+        user,
+        archive,
+        queue,
+        config.output_dir.clone(),
+        config.subscription_file.clone(),
+        config.defaults.clone(),
+        config.overrides.clone(),
+        &config.server,
+    );
+    let restored = server.restore();
+    if !restored.is_empty() {
+        info!("恢复 {} 个上次中断的任务", restored.len());
+    }
+    let listen = listen.unwrap_or(config.server.listen);
+    let listener = TcpListener::bind(listen).await?;
+    info!("控制接口已启动: http://{}", listener.local_addr()?);
+    if config.server.token.is_none() && !listen.ip().is_loopback() {
+        warn!("控制接口监听在非本机地址且未设置 token，任何人都可以提交任务");
+    }
+    axum::serve(listener, server.router())
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+            info!("收到退出信号，控制接口关闭");
+        })
+        .await?;
+    Ok(())
+}
@@ -1,60 +1,574 @@
-use std::path::Path;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
+use config::ConfigError;
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
 use tokio::fs::create_dir_all;
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table, Value, value};
 
-use crate::error::Result;
+use crate::archive::Archive;
+use crate::converter::AudioFormat;
+use crate::download::AudioQuality;
+use crate::error::{BilidownError, Result};
+use crate::filter::ContentFilter;
+use crate::mirror::{self, RemovedPolicy};
+use crate::playlist::{self, PlaylistFormat};
+use crate::report::{DownloadPlan, DownloadReport, VideoReport};
+use crate::utils;
 use crate::{VideoBasicInfo, user::User};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Subscription {
     pub bvid: String,
+    /// 订阅名称，可在 `sync --only`/`--skip` 中指定
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// 分组标签，可在 `sync --only`/`--skip` 中指定
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub artist: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub album: Option<String>,
+    /// 输出格式 `mp3` 或 `flac`，未设置时 AAC 音频转为 MP3，无损音频转为 FLAC
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<AudioFormat>,
+    /// 音质上限，未设置时下载最高音质
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<AudioQuality>,
+    /// 输出文件名模板（不含扩展名），支持通配符，可用 `/` 分隔子目录，默认为 `{title}-P{page}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    /// MP3 码率（kbps），未设置时使用高质量 VBR，对 FLAC 无效
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<u32>,
+    /// 输出目录，相对路径相对于下载目录；设置后文件直接写入该目录，不再按 BV 号建立子目录
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_dir: Option<PathBuf>,
+    /// 将视频封面嵌入音频文件
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cover: bool,
+    /// 下载 CC 字幕作为歌词，写入同名 `.lrc` 文件并嵌入音频文件
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lyrics: bool,
+    /// 额外写入的标签，值支持通配符，如 `genre = "VOCALOID"`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra_tags: BTreeMap<String, String>,
+    /// 守护模式下该订阅的同步间隔（秒），未设置时使用 `[daemon] interval`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+    /// 下载后在输出目录中生成的播放列表格式，如 `["m3u8", "xspf"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub playlist: Vec<PlaylistFormat>,
+    /// 分P从来源删除或视频失效后对本地文件的处理：`keep`、`move` 或 `delete`
+    #[serde(default, skip_serializing_if = "RemovedPolicy::is_keep")]
+    pub on_removed: RemovedPolicy,
+    /// 已下载分P的标题变化后重写其标签，分P序号变化时总会重命名文件并重写标签
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub retag: bool,
+    /// 过滤条件，在请求音频流之前根据视频信息排除不需要的视频与分P
+    #[serde(default, skip_serializing_if = "ContentFilter::is_empty")]
+    pub filter: ContentFilter,
 }
 
 impl Subscription {
-    pub async fn download(&self, user: &User, output_dir: &Path, info_only: bool) -> Result<()> {
-        let bvid = &self.bvid;
-        info!("开始处理视频: {}", bvid);
+    /// 获取视频信息与各分P可用的音频流而不下载
+    pub async fn info(&self, user: &User) -> Result<VideoReport> {
+        let video = self.fetch_video(user).await?;
+        let parts = video.list_audio_streams(user).await;
+        Ok(VideoReport { video, parts })
+    }
+
+    /// 下载视频的全部分P到 `output_dir/<bvid>`，已在下载记录中的分P会被跳过
+    ///
+    /// 有下载记录时，按 `on_removed` 处理已从视频中删除的分P；策略不是 `keep` 时，
+    /// 视频失效也不视为错误，而是处理其全部分P。配置了 `playlist` 时随后更新该目录中的播放列表。
+    pub async fn download(
+        &self,
+        user: &User,
+        output_dir: &Path,
+        archive: Option<&Archive>,
+    ) -> Result<DownloadReport> {
+        let video = match archive.filter(|_| !self.on_removed.is_keep()) {
+            None => self.fetch_video(user).await?,
+            Some(archive) => match self.find_video(user).await? {
+                Some(video) => video,
+                None => {
+                    let removed = mirror::apply(
+                        archive,
+                        archive.entries_for(&self.bvid, self.archive_scope()),
+                        self.on_removed,
+                        &mirror::removed_dir(&self.root_dir(output_dir), &self.bvid),
+                    )
+                    .await;
+                    return Ok(DownloadReport {
+                        bvid: self.bvid.clone(),
+                        title: None,
+                        tracks: Vec::new(),
+                        removed,
+                        error: None,
+                    });
+                }
+            },
+        };
+        // 没有分P信息时无法判断哪些分P已删除，不能处理下载记录
+        let Some(pages) = &video.pages else {
+            return Err(BilidownError::ApiError("视频没有分P信息".to_string()));
+        };
+        // 视频被排除时不创建目录、不处理下载记录，也不获取封面
+        if let Some(reason) = self.filter.compile()?.video_reason(&video) {
+            info!("视频 {} 被过滤条件排除: {}", video.bvid, reason);
+            return Ok(DownloadReport {
+                tracks: video.filtered_tracks(user),
+                bvid: video.bvid,
+                title: Some(video.title),
+                removed: Vec::new(),
+                error: None,
+            });
+        }
+        let video_dir = self.video_dir(output_dir);
+        create_dir_all(&video_dir).await?;
+        info!("开始下载音频到目录: {:?}", video_dir);
+        // 先处理已删除的分P，播放列表中不再包含它们
+        let removed = match archive {
+            Some(archive) => {
+                let cids: Vec<i64> = pages.iter().map(|part| part.cid).collect();
+                let entries = archive
+                    .entries_for(&self.bvid, self.archive_scope())
+                    .into_iter()
+                    .filter(|entry| !cids.contains(&entry.cid))
+                    .collect();
+                mirror::apply(
+                    archive,
+                    entries,
+                    self.on_removed,
+                    &mirror::removed_dir(&self.root_dir(output_dir), &self.bvid),
+                )
+                .await
+            }
+            None => Vec::new(),
+        };
+        // 下载音频
+        let tracks = video
+            .download_best_quality_audios_to_file(user, &video_dir, self, archive)
+            .await?;
+        playlist::update(&video, self, &tracks, &video_dir);
+        info!("下载完成!");
+        Ok(DownloadReport {
+            bvid: video.bvid,
+            title: Some(video.title),
+            tracks,
+            removed,
+            error: None,
+        })
+    }
 
+    /// 预演下载，列出将要下载、跳过与覆盖的分P，不创建目录也不下载音频
+    pub async fn plan(
+        &self,
+        user: &User,
+        output_dir: &Path,
+        archive: Option<&Archive>,
+    ) -> Result<DownloadPlan> {
+        let video = self.fetch_video(user).await?;
+        let tracks = video
+            .plan_downloads(user, &self.video_dir(output_dir), self, archive)
+            .await;
+        Ok(DownloadPlan {
+            bvid: video.bvid,
+            title: Some(video.title),
+            tracks,
+            error: None,
+        })
+    }
+
+    /// 名称、任一标签或 BV 号与 `selector` 相同
+    pub fn matches(&self, selector: &str) -> bool {
+        self.name.as_deref() == Some(selector)
+            || self.tags.iter().any(|tag| tag == selector)
+            || self.bvid == selector
+    }
+
+    /// 同一视频可以订阅到不同的输出目录，以 BV 号与输出目录区分订阅
+    pub fn key(&self) -> String {
+        match &self.output_dir {
+            Some(dir) => format!("{}@{}", self.bvid, dir.display()),
+            None => self.bvid.clone(),
+        }
+    }
+
+    /// 下载记录中区分输出目录的范围
+    pub fn archive_scope(&self) -> Option<&Path> {
+        self.output_dir.as_deref()
+    }
+
+    /// 订阅的根目录，来源删除的文件移动到其中的 `removed/<bvid>`
+    pub fn root_dir(&self, output_dir: &Path) -> PathBuf {
+        match &self.output_dir {
+            Some(dir) => output_dir.join(dir),
+            None => output_dir.to_path_buf(),
+        }
+    }
+
+    /// 输出文件所在目录，未设置 `output_dir` 时为 `output_dir/<bvid>`
+    pub fn video_dir(&self, output_dir: &Path) -> PathBuf {
+        match &self.output_dir {
+            Some(dir) => output_dir.join(dir),
+            None => output_dir.join(crate::utils::sanitize_filename(&self.bvid)),
+        }
+    }
+
+    async fn fetch_video(&self, user: &User) -> Result<VideoBasicInfo> {
+        info!("开始处理视频: {}", self.bvid);
         debug!("正在获取视频信息...");
-        let video = VideoBasicInfo::new_from_bvid(&user, bvid).await?;
+        let video = VideoBasicInfo::new_from_subscription(user, self).await?;
         info!(
             "视频信息获取成功: {} ({} - {})",
             video.title, video.owner.name, video.bvid
         );
-        if info_only {
-            // 仅显示视频信息
-            info!("以信息模式运行，不下载音频");
-            println!("视频信息:");
-            println!("标题: {}", video.title);
-            println!("UP主: {}", video.owner.name);
-            println!("播放数: {}", video.stat.view);
-            println!("时长: {}秒", video.duration);
-            if let Some(pages) = &video.pages {
-                println!("分P数: {}", pages.len());
-                for page in pages {
-                    println!("  - P{}: {} ({}秒)", page.page, page.part, page.duration);
+        Ok(video)
+    }
+
+    /// 获取视频信息，视频已失效时返回 `None`
+    async fn find_video(&self, user: &User) -> Result<Option<VideoBasicInfo>> {
+        info!("开始处理视频: {}", self.bvid);
+        let video = VideoBasicInfo::find_by_bvid(user, &self.bvid).await?;
+        if let Some(video) = &video {
+            info!(
+                "视频信息获取成功: {} ({} - {})",
+                video.title, video.owner.name, video.bvid
+            );
+        }
+        Ok(video)
+    }
+
+    /// 在订阅文件末尾追加一条订阅，保留文件中已有的注释与顺序
+    ///
+    /// 视频已在该文件或其包含的文件中订阅到同一输出目录时返回错误
+    pub fn add_to_file(&self, path: &Path) -> Result<()> {
+        let output_dir = self.output_dir.as_ref().map(|dir| dir.to_string_lossy());
+        for file in subscription_files(path)? {
+            let doc = read_document(&file)?;
+            let subs = doc.get("sub").and_then(Item::as_array_of_tables);
+            if subs.iter().flat_map(|subs| subs.iter()).any(|table| {
+                is_subscription_of(table, &self.bvid)
+                    && table.get("output_dir").and_then(Item::as_str) == output_dir.as_deref()
+            }) {
+                return Err(BilidownError::ArgumentError(format!(
+                    "{} 已在订阅文件 {} 中，可使用 subs set 修改",
+                    self.bvid,
+                    file.display()
+                )));
+            }
+        }
+
+        let mut doc = read_document(path)?;
+        let subs = doc
+            .entry("sub")
+            .or_insert_with(|| Item::ArrayOfTables(ArrayOfTables::new()))
+            .as_array_of_tables_mut()
+            .ok_or_else(|| invalid_file(path, "sub 必须是 [[sub]] 表数组"))?;
+
+        let mut table = Table::new();
+        table.insert("bvid", value(&self.bvid));
+        for (key, template) in [
+            ("title", &self.title),
+            ("artist", &self.artist),
+            ("album", &self.album),
+        ] {
+            if let Some(template) = template {
+                table.insert(key, value(template));
+            }
+        }
+        if let Some(interval) = self.interval {
+            table.insert("interval", value(interval as i64));
+        }
+        if let Some(output_dir) = output_dir {
+            table.insert("output_dir", value(output_dir.as_ref()));
+        }
+        subs.push(table);
+        write_document(path, &doc)
+    }
+
+    /// 修改订阅文件及其包含的文件中指定视频的订阅，保留其余字段与注释，返回修改的条数
+    ///
+    /// 订阅在哪个文件中定义就修改哪个文件
+    pub fn edit_in_file(path: &Path, bvid: &str, edit: &SubscriptionEdit) -> Result<usize> {
+        if let Some(key) = edit
+            .unset
+            .iter()
+            .find(|key| !SubscriptionEdit::KEYS.contains(&key.as_str()))
+        {
+            return Err(BilidownError::ArgumentError(format!(
+                "无法删除字段 {}，可删除的字段: {}",
+                key,
+                SubscriptionEdit::KEYS.join(", ")
+            )));
+        }
+        let mut edited = 0;
+        for file in subscription_files(path)? {
+            edited += update_subs(&file, |subs| {
+                let mut edited = 0;
+                for table in subs
+                    .iter_mut()
+                    .filter(|table| is_subscription_of(table, bvid))
+                {
+                    for key in &edit.unset {
+                        table.remove(key);
+                    }
+                    for (key, template) in [
+                        ("title", &edit.title),
+                        ("artist", &edit.artist),
+                        ("album", &edit.album),
+                    ] {
+                        if let Some(template) = template {
+                            set_value(table, key, template.as_str().into());
+                        }
+                    }
+                    if let Some(interval) = edit.interval {
+                        set_value(table, "interval", (interval as i64).into());
+                    }
+                    edited += 1;
                 }
+                edited
+            })?;
+        }
+        Ok(edited)
+    }
+
+    /// 从订阅文件及其包含的文件中删除指定视频的全部订阅，返回删除的条数
+    pub fn remove_from_file(path: &Path, bvid: &str) -> Result<usize> {
+        let mut removed = 0;
+        for file in subscription_files(path)? {
+            removed += update_subs(&file, |subs| {
+                let before = subs.len();
+                subs.retain(|table| !is_subscription_of(table, bvid));
+                before - subs.len()
+            })?;
+        }
+        Ok(removed)
+    }
+}
+
+/// 订阅文件本身及其通过 `include` 包含的全部文件，不存在的文件与重复包含的文件会被跳过
+fn subscription_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![path.to_path_buf()];
+    let mut seen = vec![canonical(path)];
+    let mut index = 0;
+    while let Some(file) = files.get(index).cloned() {
+        index += 1;
+        let doc = read_document(&file)?;
+        let include = doc.get("include").and_then(Item::as_array);
+        for included in include.iter().flat_map(|include| include.iter()) {
+            let Some(included) = included.as_str() else {
+                continue;
+            };
+            let included = crate::config::include_path(&file, Path::new(included));
+            if included.is_file() && !seen.contains(&canonical(&included)) {
+                seen.push(canonical(&included));
+                files.push(included);
             }
-        } else {
-            let safe_bvid = crate::utils::sanitize_filename(&self.bvid);
-            let output_dir = output_dir.join(&safe_bvid);
-            create_dir_all(&output_dir).await?;
-            info!("开始下载音频到目录: {:?}", output_dir);
-            // 下载音频
-            video
-                .download_best_quality_audios_to_file(&user, &output_dir, &self)
-                .await?;
-            info!("下载完成!");
-            println!("下载完成!");
-        }
-        Ok(())
+        }
+    }
+    Ok(files)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// 修改订阅文件中的 `[[sub]]` 表数组，`update` 返回修改的条数，有修改时写回文件
+fn update_subs(path: &Path, update: impl FnOnce(&mut ArrayOfTables) -> usize) -> Result<usize> {
+    let mut doc = read_document(path)?;
+    let Some(subs) = doc.get_mut("sub").and_then(Item::as_array_of_tables_mut) else {
+        return Ok(0);
+    };
+    let updated = update(subs);
+    if updated > 0 {
+        write_document(path, &doc)?;
+    }
+    Ok(updated)
+}
+
+/// 通过 `subs set` 修改订阅时的字段变更，未设置的字段保持不变
+#[derive(Debug, Clone, Default)]
+pub struct SubscriptionEdit {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub interval: Option<u64>,
+    /// 要删除的字段，删除后恢复默认行为
+    pub unset: Vec<String>,
+}
+
+impl SubscriptionEdit {
+    /// 可删除的字段
+    pub const KEYS: &[&str] = &["title", "artist", "album", "interval"];
+}
+
+fn is_subscription_of(table: &Table, bvid: &str) -> bool {
+    table.get("bvid").and_then(Item::as_str) == Some(bvid)
+}
+
+/// 替换字段的值，保留原值前后的空白与行尾注释
+fn set_value(table: &mut Table, key: &str, mut new: Value) {
+    match table.get_mut(key).and_then(Item::as_value_mut) {
+        Some(old) => {
+            *new.decor_mut() = old.decor().clone();
+            *old = new;
+        }
+        None => {
+            table.insert(key, Item::Value(new));
+        }
+    }
+}
+
+fn invalid_file(path: &Path, reason: impl std::fmt::Display) -> BilidownError {
+    ConfigError::Message(format!("订阅文件 {} 无效: {}", path.display(), reason)).into()
+}
+
+fn read_document(path: &Path) -> Result<DocumentMut> {
+    if !path.exists() {
+        return Ok(DocumentMut::new());
+    }
+    std::fs::read_to_string(path)?
+        .parse::<DocumentMut>()
+        .map_err(|e| invalid_file(path, e))
+}
+
+/// 先写临时文件再替换，写入中断时不会截断手动编辑的订阅文件
+fn write_document(path: &Path, doc: &DocumentMut) -> Result<()> {
+    utils::write_file_atomic(path, doc.to_string().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_remove_preserve_comments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub.toml");
+        std::fs::write(
+            &path,
+            "# 我的订阅\n[[sub]]\nbvid = \"BV1H242zQEyb\" # 洛天依\ntitle = \"{title}\"\n",
+        )
+        .unwrap();
+
+        let sub = Subscription {
+            bvid: "BV1NfxMedEU6".to_string(),
+            title: None,
+            artist: Some("{artist}".to_string()),
+            ..Default::default()
+        };
+        sub.add_to_file(&path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("# 我的订阅\n"));
+        assert!(contents.contains("bvid = \"BV1H242zQEyb\" # 洛天依"));
+        assert!(contents.contains("[[sub]]\nbvid = \"BV1NfxMedEU6\"\nartist = \"{artist}\"\n"));
+        assert!(!dir.path().join("sub.toml.tmp").exists());
+
+        assert_eq!(
+            Subscription::remove_from_file(&path, "BV1H242zQEyb").unwrap(),
+            1
+        );
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("BV1H242zQEyb"));
+        assert!(contents.contains("BV1NfxMedEU6"));
+        assert!(matches!(
+            sub.add_to_file(&path),
+            Err(BilidownError::ArgumentError(_))
+        ));
+    }
+
+    #[test]
+    fn test_edit_preserves_comments_and_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub.toml");
+        std::fs::write(
+            &path,
+            "[[sub]]\nbvid = \"BV1H242zQEyb\"\ntitle = \"{title}\" # 歌名\nalbum = \"旧专辑\"\n\n\
+             # 第二个\n[[sub]]\nbvid = \"BV1NfxMedEU6\"\n",
+        )
+        .unwrap();
+
+        let edit = SubscriptionEdit {
+            title: Some("{part_title}".to_string()),
+            artist: Some("洛天依".to_string()),
+            interval: Some(3600),
+            unset: vec!["album".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            Subscription::edit_in_file(&path, "BV1H242zQEyb", &edit).unwrap(),
+            1
+        );
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "[[sub]]\nbvid = \"BV1H242zQEyb\"\ntitle = \"{part_title}\" # 歌名\n\
+             artist = \"洛天依\"\ninterval = 3600\n\n\
+             # 第二个\n[[sub]]\nbvid = \"BV1NfxMedEU6\"\n"
+        );
+
+        let invalid = SubscriptionEdit {
+            unset: vec!["bvid".to_string()],
+            ..Default::default()
+        };
+        assert!(Subscription::edit_in_file(&path, "BV1H242zQEyb", &invalid).is_err());
+        assert_eq!(
+            Subscription::edit_in_file(&path, "BV1fk4y1x7Aa", &edit).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_edits_the_file_that_defines_the_subscription() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub.toml");
+        let main = "include = [\"alice.toml\"]\n\n[[sub]]\nbvid = \"BV1H242zQEyb\"\n";
+        std::fs::write(&path, main).unwrap();
+        let included = dir.path().join("alice.toml");
+        std::fs::write(
+            &included,
+            "include = [\"sub.toml\"]\n\n[[sub]]\nbvid = \"BV1NfxMedEU6\" # 包含的订阅\n",
+        )
+        .unwrap();
+
+        let edit = SubscriptionEdit {
+            artist: Some("洛天依".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            Subscription::edit_in_file(&path, "BV1NfxMedEU6", &edit).unwrap(),
+            1
+        );
+        let contents = std::fs::read_to_string(&included).unwrap();
+        assert!(contents.contains("bvid = \"BV1NfxMedEU6\" # 包含的订阅\nartist = \"洛天依\"\n"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), main);
+
+        let sub = Subscription {
+            bvid: "BV1NfxMedEU6".to_string(),
+            ..Default::default()
+        };
+        match sub.add_to_file(&path) {
+            Err(BilidownError::ArgumentError(message)) => assert!(message.contains("alice.toml")),
+            other => panic!("expected ArgumentError, got {:?}", other),
+        }
+
+        assert_eq!(
+            Subscription::remove_from_file(&path, "BV1NfxMedEU6").unwrap(),
+            1
+        );
+        assert!(
+            !std::fs::read_to_string(&included)
+                .unwrap()
+                .contains("BV1NfxMedEU6")
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), main);
     }
 }
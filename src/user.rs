@@ -1,35 +1,73 @@
 use qrcode::{QrCode, render::unicode};
-use reqwest::{Client, RequestBuilder};
-use std::{io, path::PathBuf, time::Duration};
+use reqwest::{Client, RequestBuilder, Response, StatusCode, header::RANGE};
+use std::{
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 use tokio::{
-    fs::{read_to_string, write},
-    sync::OnceCell,
+    fs::{File, OpenOptions, read_to_string},
+    io::AsyncWriteExt,
+    sync::Mutex,
     time::sleep,
 };
 
 use crate::{
-    api::{client::ApiClient, endpoints},
+    api::{
+        client::{ApiClient, EndpointConfig},
+        endpoints,
+        identity::Identity,
+    },
     config::AppConfig,
     error::{BilidownError, Result},
+    limit::{LimitConfig, Limiter},
+    progress::Progress,
+    wbi::WbiKeys,
 };
 use log::{debug, error, info, warn};
 
+/// bili_ticket 续期失败后，签名请求在该时间内不再重复尝试
+const TICKET_RETRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 pub struct User {
     api_client: ApiClient,
-    wbi_keys: OnceCell<(String, String)>,
+    wbi_keys: Mutex<Option<WbiKeys>>,
+    wbi_cache_file: Option<PathBuf>,
+    identity_file: Option<PathBuf>,
+    ticket_failed_at: Mutex<Option<Instant>>, // 最近一次续期 bili_ticket 失败的时间
+    anonymous: bool,
+    progress: Progress,
 }
 
 impl User {
-    pub async fn new() -> Result<Self> {
-        let mut user = Self {
-            api_client: ApiClient::new(Vec::new()),
-            wbi_keys: OnceCell::new(),
-        };
+    fn from_client(api_client: ApiClient, anonymous: bool) -> Self {
+        Self {
+            api_client,
+            wbi_keys: Mutex::new(None),
+            wbi_cache_file: None,
+            identity_file: None,
+            ticket_failed_at: Mutex::new(None),
+            anonymous,
+            progress: Progress::default(),
+        }
+    }
+
+    pub async fn new(endpoints: EndpointConfig, proxy: Option<&str>) -> Result<Self> {
+        let mut user = Self::from_client(ApiClient::new(Vec::new(), endpoints, proxy), false);
         user.login().await?;
         Ok(user)
     }
 
-    pub async fn new_from_file(file_path: &PathBuf) -> Result<Self> {
+    /// 创建游客用户，跳过登录，仅能获取游客可用的音质
+    pub fn new_anonymous(endpoints: EndpointConfig, proxy: Option<&str>) -> Self {
+        Self::from_client(ApiClient::new(Vec::new(), endpoints, proxy), true)
+    }
+
+    pub async fn new_from_file(
+        file_path: &PathBuf,
+        endpoints: EndpointConfig,
+        proxy: Option<&str>,
+    ) -> Result<Self> {
         let contents = read_to_string(file_path).await.map_err(|e| {
             BilidownError::LoginError(format!(
                 "无法读取文件 {}: {}",
@@ -48,10 +86,7 @@ impl User {
                 file_path.to_string_lossy()
             )));
         }
-        let ret = Self {
-            api_client: ApiClient::new(cookies),
-            wbi_keys: OnceCell::new(),
-        };
+        let ret = Self::from_client(ApiClient::new(cookies, endpoints, proxy), false);
         if ret.verify_login().await? {
             Ok(ret)
         } else {
@@ -62,8 +97,77 @@ impl User {
     }
 
     pub async fn ensure_user(config: &AppConfig) -> Result<Self> {
+        let user = if config.anonymous {
+            info!("以游客模式运行，跳过登录");
+            User::new_anonymous(config.endpoints.clone(), config.defaults.proxy.as_deref())
+        } else {
+            Self::load_or_login(config).await?
+        };
+        Ok(user.configured(config).await)
+    }
+
+    /// 应用配置中的限制、缓存与设备标识文件
+    async fn configured(mut self, config: &AppConfig) -> Self {
+        self.set_limits(&config.limits);
+        self.wbi_cache_file = Some(config.wbi_cache_file.clone());
+        self.identity_file = Some(config.identity_file.clone());
+        if let Some(identity) = Identity::load(&config.identity_file).await {
+            self.api_client.set_identity(identity);
+        }
+        self.refresh_identity().await;
+        self
+    }
+
+    /// 供长时间运行时定期调用：登录过期或当前为游客时重新读取 Cookie 文件，并续期 bili_ticket
+    ///
+    /// Cookie 文件仍然无效时以游客身份继续，不会发起扫码登录
+    pub async fn refresh_session(&mut self, config: &AppConfig) {
+        if !config.anonymous {
+            let expired = self.anonymous
+                || match self.verify_login().await {
+                    Ok(logged_in) => !logged_in,
+                    Err(e) => {
+                        warn!("检查登录状态失败: {}", e);
+                        false
+                    }
+                };
+            if expired {
+                match User::new_from_file(
+                    &config.cookie_file,
+                    config.endpoints.clone(),
+                    config.defaults.proxy.as_deref(),
+                )
+                .await
+                {
+                    Ok(user) => {
+                        info!("已重新加载Cookie: {}", config.cookie_file.to_string_lossy());
+                        let progress = self.progress.clone();
+                        *self = user.configured(config).await;
+                        self.progress = progress;
+                        return;
+                    }
+                    Err(e) if !self.anonymous => {
+                        warn!("登录已过期且无法重新加载Cookie: {}，将以游客模式继续", e);
+                        self.anonymous = true;
+                        // 不再携带失效的登录 Cookie，否则请求仍会以过期的身份发出
+                        self.api_client.cookies.clear();
+                    }
+                    Err(e) => debug!("仍无法从文件加载用户: {}", e),
+                }
+            }
+        }
+        self.refresh_identity().await;
+    }
+
+    async fn load_or_login(config: &AppConfig) -> Result<Self> {
         // 从配置文件加载用户或新建用户
-        let user = match User::new_from_file(&config.cookie_file).await {
+        let user = match User::new_from_file(
+            &config.cookie_file,
+            config.endpoints.clone(),
+            config.defaults.proxy.as_deref(),
+        )
+        .await
+        {
             Ok(u) => {
                 info!(
                     "从文件加载用户信息: {}",
@@ -71,23 +175,35 @@ impl User {
                 );
                 u
             }
+            Err(e) if !io::stdout().is_terminal() => {
+                // 无法在非交互环境中扫码，只有明确指定游客模式时才以游客身份运行
+                return Err(BilidownError::LoginError(format!(
+                    "从文件加载用户失败: {}，当前不是交互终端，无法扫码登录；请先执行 login 或使用 --anonymous",
+                    e
+                )));
+            }
             Err(e) => {
                 info!("从文件加载用户失败: {}，将进行二维码登录", e);
                 info!("未找到现有cookie或cookie无效，正在进行二维码登录...");
-                let u = User::new()
-                    .await
-                    .map_err(|e| BilidownError::LoginError(e.to_string()))?;
-                u.save_to_file(&config.cookie_file)?;
-                info!(
-                    "登录成功，cookie已保存到: {}",
-                    &config.cookie_file.to_string_lossy()
-                );
-                u
+                Self::login_and_save(config).await?
             }
         };
         Ok(user)
     }
 
+    /// 进行二维码登录并将 Cookie 保存到配置的文件
+    pub async fn login_and_save(config: &AppConfig) -> Result<Self> {
+        let user = User::new(config.endpoints.clone(), config.defaults.proxy.as_deref())
+            .await
+            .map_err(|e| BilidownError::LoginError(e.to_string()))?;
+        user.save_to_file(&config.cookie_file)?;
+        info!(
+            "登录成功，cookie已保存到: {}",
+            &config.cookie_file.to_string_lossy()
+        );
+        Ok(user)
+    }
+
     pub fn save_to_file(&self, file_name: &PathBuf) -> io::Result<()> {
         let contents = self.api_client.cookies.join("\n");
         // 保证路径
@@ -191,39 +307,210 @@ impl User {
         Ok(())
     }
 
-    pub async fn get_wbi_keys(&self) -> (&str, &str) {
-        let wbi_keys = self
-            .wbi_keys
-            .get_or_init(|| async {
-                crate::wbi::get_wbi_keys().await.unwrap_or_else(|e| {
-                    error!("获取WBI密钥失败: {}", e);
-                    panic!("无法获取WBI密钥，程序退出");
-                })
-            })
-            .await;
-        (&wbi_keys.0, &wbi_keys.1)
+    /// 获取 WBI 密钥，依次尝试内存缓存、磁盘缓存，均过期时重新请求
+    pub async fn get_wbi_keys(&self) -> Result<(String, String)> {
+        let mut wbi_keys = self.wbi_keys.lock().await;
+        if let Some(keys) = wbi_keys.as_ref().filter(|keys| !keys.is_expired()) {
+            return Ok((keys.img_key.clone(), keys.sub_key.clone()));
+        }
+        if let Some(path) = &self.wbi_cache_file
+            && let Some(keys) = WbiKeys::load(path).await.filter(|keys| !keys.is_expired())
+        {
+            debug!("从缓存文件加载WBI密钥: {}", path.to_string_lossy());
+            let ret = (keys.img_key.clone(), keys.sub_key.clone());
+            *wbi_keys = Some(keys);
+            return Ok(ret);
+        }
+        let keys = self.fetch_wbi_keys().await?;
+        let ret = (keys.img_key.clone(), keys.sub_key.clone());
+        *wbi_keys = Some(keys);
+        Ok(ret)
+    }
+
+    /// 丢弃已缓存的 WBI 密钥并重新请求，用于签名校验失败时
+    pub async fn refresh_wbi_keys(&self) -> Result<()> {
+        let mut wbi_keys = self.wbi_keys.lock().await;
+        *wbi_keys = Some(self.fetch_wbi_keys().await?);
+        Ok(())
+    }
+
+    async fn fetch_wbi_keys(&self) -> Result<WbiKeys> {
+        debug!("正在请求WBI密钥...");
+        let keys = crate::wbi::get_wbi_keys(self).await.map_err(|e| {
+            error!("获取WBI密钥失败: {}", e);
+            e
+        })?;
+        if let Some(path) = &self.wbi_cache_file
+            && let Err(e) = keys.save(path).await
+        {
+            warn!("保存WBI密钥缓存失败: {}", e);
+        }
+        Ok(keys)
+    }
+
+    pub fn identity(&self) -> Identity {
+        self.api_client.identity()
+    }
+
+    /// 补全 buvid4 并在 bili_ticket 过期时续期，失败时仅记录警告
+    pub async fn refresh_identity(&self) {
+        let mut identity = self.api_client.identity();
+        if identity.buvid4.is_none() {
+            match endpoints::get_buvid(self).await {
+                Ok(spi) => {
+                    debug!("获取到设备标识 buvid4: {}", spi.b_4);
+                    identity.buvid4 = Some(spi.b_4);
+                }
+                Err(e) => warn!("获取buvid4失败: {}", e),
+            }
+        }
+        if identity.ticket_expired() {
+            let csrf = self.api_client.cookie_value("bili_jct").unwrap_or_default();
+            match endpoints::gen_web_ticket(self, csrf).await {
+                Ok(ticket) => {
+                    debug!("bili_ticket 已更新，有效期 {} 秒", ticket.ttl);
+                    identity.bili_ticket = Some(ticket.ticket);
+                    identity.bili_ticket_expires = Some(ticket.created_at + ticket.ttl);
+                }
+                Err(e) => warn!("获取bili_ticket失败: {}", e),
+            }
+        }
+        if let Some(path) = &self.identity_file
+            && let Err(e) = identity.save(path).await
+        {
+            warn!("保存设备标识失败: {}", e);
+        }
+        self.api_client.set_identity(identity);
+    }
+
+    /// 签名请求前调用：bili_ticket 过期时续期
+    ///
+    /// 并发请求只会有一个发起续期，续期失败后在 [`TICKET_RETRY_INTERVAL`] 内不再重试
+    pub async fn renew_ticket(&self) {
+        if !self.identity().ticket_expired() {
+            return;
+        }
+        let mut failed_at = self.ticket_failed_at.lock().await;
+        // 等待锁期间可能已被其他请求续期
+        if !self.identity().ticket_expired()
+            || failed_at.is_some_and(|at| at.elapsed() < TICKET_RETRY_INTERVAL)
+        {
+            return;
+        }
+        self.refresh_identity().await;
+        *failed_at = self.identity().ticket_expired().then(Instant::now);
+    }
+
+    /// 是否为未登录的游客用户
+    pub fn is_anonymous(&self) -> bool {
+        self.anonymous
     }
 
     pub fn get_client(&self) -> &Client {
         &self.api_client.client
     }
 
+    /// 设置并发与请求频率限制，替换默认限制
+    pub fn set_limits(&mut self, limits: &LimitConfig) {
+        self.api_client.set_limits(limits);
+    }
+
+    pub fn limiter(&self) -> &Limiter {
+        self.api_client.limiter()
+    }
+
+    /// 下载进度事件，可通过 `subscribe` 或 `channel` 接收
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
     pub fn get(&self, url: &str) -> RequestBuilder {
         self.api_client.get(url)
     }
 
-    pub async fn download_to_file(&self, url: &str, path: &PathBuf, file_name: &str) -> Result<()> {
-        let req = self.get(url);
-        let resp = req.send().await?;
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.api_client.post(url)
+    }
+
+    /// 在接口并发与频率限制内发送请求
+    pub async fn send(&self, req: RequestBuilder) -> Result<Response> {
+        let request = req.build()?;
+        let _permit = self.limiter().api(request.url().as_str()).await;
+        Ok(self.get_client().execute(request).await?)
+    }
+
+    /// 拼接主站接口地址
+    pub fn api_url(&self, path: &str) -> String {
+        self.api_client.endpoints.api_url(path)
+    }
+
+    /// 拼接登录接口地址
+    pub fn passport_url(&self, path: &str) -> String {
+        self.api_client.endpoints.passport_url(path)
+    }
+
+    /// 获取封面、字幕等静态资源的内容
+    pub async fn get_bytes(&self, url: &str) -> Result<Vec<u8>> {
+        let req = self.get(&self.api_client.endpoints.rewrite_cdn(url));
+        let resp = self.send(req).await?;
         if !resp.status().is_success() {
             return Err(BilidownError::ApiError(format!(
-                "下载失败，HTTP状态码: {}",
+                "请求 {} 失败，HTTP状态码: {}",
+                url,
                 resp.status()
             )));
         }
-        let bytes = resp.bytes().await?;
+        Ok(resp.bytes().await?.to_vec())
+    }
+
+    /// 以流式方式下载文件，每收到一块数据调用一次 `on_progress(已下载字节数, 总字节数)`
+    ///
+    /// 目标文件已存在时视为上次中断的部分下载，通过 `Range` 请求从已有长度处继续；
+    /// 服务器不支持续传时重新下载整个文件。
+    pub async fn download_to_file(
+        &self,
+        url: &str,
+        path: &Path,
+        file_name: &str,
+        on_progress: impl Fn(u64, Option<u64>),
+    ) -> Result<()> {
         let file_path = path.join(file_name);
-        write(file_path, bytes).await?;
+        let existing = tokio::fs::metadata(&file_path)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        let mut req = self.get(&self.api_client.endpoints.rewrite_cdn(url));
+        if existing > 0 {
+            debug!("从 {} 字节处继续下载 {}", existing, file_name);
+            req = req.header(RANGE, format!("bytes={}-", existing));
+        }
+        let _permit = self.limiter().download().await;
+        let mut resp = req.send().await?;
+        if existing > 0 && resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // 上次已下载完整，只是尚未处理
+            on_progress(existing, Some(existing));
+            return Ok(());
+        }
+        if !resp.status().is_success() {
+            return Err(BilidownError::ApiError(format!(
+                "下载失败，HTTP状态码: {}",
+                resp.status()
+            )));
+        }
+        let (mut file, mut downloaded) = if resp.status() == StatusCode::PARTIAL_CONTENT {
+            let file = OpenOptions::new().append(true).open(&file_path).await?;
+            (file, existing)
+        } else {
+            (File::create(&file_path).await?, 0)
+        };
+        let total = resp.content_length().map(|len| len + downloaded);
+        on_progress(downloaded, total);
+        while let Some(chunk) = resp.chunk().await? {
+            file.write_all(&chunk).await?;
+            downloaded += chunk.len() as u64;
+            on_progress(downloaded, total);
+        }
+        file.flush().await?;
         Ok(())
     }
 }
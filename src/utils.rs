@@ -1,6 +1,6 @@
 //! Utility functions for the application
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use tokio::process::Command;
 
@@ -37,6 +37,16 @@ pub fn sanitize_filename(filename: &str) -> String {
         .replace("\\", "_")
 }
 
+/// 从 BV 号或视频链接中提取 BV 号，如 `https://www.bilibili.com/video/BV1NfxMedEU6/?p=2`
+pub fn extract_bvid(input: &str) -> Option<String> {
+    let start = input.find("BV")?;
+    let bvid: String = input[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect();
+    (bvid.len() == 12).then_some(bvid)
+}
+
 pub fn validate_file_exists(path: &Path) -> Result<()> {
     if !path.exists() {
         return Err(BilidownError::IoError(std::io::Error::new(
@@ -48,8 +58,8 @@ pub fn validate_file_exists(path: &Path) -> Result<()> {
 }
 
 pub fn validate_file_not_empty(path: &Path) -> Result<()> {
-    let metadata = std::fs::metadata(path)
-        .map_err(|e| BilidownError::IoError(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
+    let metadata =
+        std::fs::metadata(path).map_err(|e| BilidownError::IoError(std::io::Error::other(e)))?;
 
     if metadata.len() == 0 {
         return Err(BilidownError::ConversionError(format!(
@@ -60,3 +70,54 @@ pub fn validate_file_not_empty(path: &Path) -> Result<()> {
 
     Ok(())
 }
+
+/// 先写入同目录下的临时文件再重命名到目标位置，写入中断时原文件保持完整
+pub fn write_file_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let temp = path.with_file_name(name);
+    std::fs::write(&temp, contents)?;
+    std::fs::rename(&temp, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp);
+    })?;
+    Ok(())
+}
+
+/// 在阻塞线程中执行 [`write_file_atomic`]，不占用异步运行时
+pub async fn write_atomic(path: &Path, contents: String) -> Result<()> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || write_file_atomic(&path, contents.as_bytes()))
+        .await
+        .map_err(|e| BilidownError::IoError(std::io::Error::other(e)))?
+}
+
+/// 将无法解析的文件移动到同目录下的 `<文件名>.corrupt`，返回新位置
+pub fn move_aside(path: &Path) -> Result<PathBuf> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".corrupt");
+    let target = path.with_file_name(name);
+    std::fs::rename(path, &target)?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_bvid() {
+        assert_eq!(
+            extract_bvid("BV1NfxMedEU6").as_deref(),
+            Some("BV1NfxMedEU6")
+        );
+        assert_eq!(
+            extract_bvid("https://www.bilibili.com/video/BV1NfxMedEU6/?p=2").as_deref(),
+            Some("BV1NfxMedEU6")
+        );
+        assert_eq!(extract_bvid("https://b23.tv/abc"), None);
+        assert_eq!(extract_bvid("BV123"), None);
+    }
+}
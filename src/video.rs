@@ -1,11 +1,18 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, path::Path};
 
 use crate::{
     api::endpoints,
-    converter::{convert_audio_with_metadata, validate_converted_file},
+    archive::{Archive, ArchiveEntry},
+    converter::{
+        TrackTags, add_metadata_to_file, convert_audio, embed_cover_and_lyrics, output_format,
+        output_path, renumbered_path, validate_converted_file,
+    },
     download::DashAudioStream,
     error::{BilidownError, Result},
-    models::VideoBasicInfo,
+    lyrics,
+    models::{VideoBasicInfo, VideoPart},
+    progress::ProgressEvent,
+    report::{PartStreams, PlanAction, PlannedTrack, TrackResult, TrackStatus},
     subscription::Subscription,
     user::User,
 };
@@ -14,102 +21,551 @@ use log::{debug, error, info, warn};
 
 impl VideoBasicInfo {
     pub async fn new_from_bvid(user: &User, bvid: &str) -> Result<Self> {
-        endpoints::get_video_info(user, bvid).await
+        let video = endpoints::get_video_info(user, bvid).await?;
+        user.progress().emit(ProgressEvent::VideoResolved {
+            bvid: video.bvid.clone(),
+            title: video.title.clone(),
+            parts: video.pages.as_ref().map_or(0, Vec::len),
+        });
+        Ok(video)
     }
 
     pub async fn new_from_subscription(user: &User, subscription: &Subscription) -> Result<Self> {
         Self::new_from_bvid(user, &subscription.bvid).await
     }
 
+    /// 与 [`Self::new_from_bvid`] 相同，但稿件已删除或不可见时返回 `None`
+    pub async fn find_by_bvid(user: &User, bvid: &str) -> Result<Option<Self>> {
+        let Some(video) = endpoints::find_video_info(user, bvid).await? else {
+            return Ok(None);
+        };
+        user.progress().emit(ProgressEvent::VideoResolved {
+            bvid: video.bvid.clone(),
+            title: video.title.clone(),
+            parts: video.pages.as_ref().map_or(0, Vec::len),
+        });
+        Ok(Some(video))
+    }
+
+    /// 获取全部分P可用的音频流，单个分P失败时记录在结果中
+    pub async fn list_audio_streams(&self, user: &User) -> Vec<PartStreams> {
+        let Some(pages) = &self.pages else {
+            return Vec::new();
+        };
+        let tasks = pages.iter().map(|video_part| async move {
+            let streams = video_part.get_dash_audio_stream(&self.bvid, user).await;
+            PartStreams::new(video_part, streams)
+        });
+        future::join_all(tasks).await
+    }
+
+    /// 预演下载：选择音频流并计算标签与输出路径，但不下载音频
+    pub async fn plan_downloads(
+        &self,
+        user: &User,
+        dir: &Path,
+        subscription: &Subscription,
+        archive: Option<&Archive>,
+    ) -> Vec<PlannedTrack> {
+        let Some(pages) = &self.pages else {
+            return Vec::new();
+        };
+        let filter = match subscription.filter.compile() {
+            Ok(filter) => filter,
+            Err(e) => {
+                return pages
+                    .iter()
+                    .map(|video_part| {
+                        let mut track = PlannedTrack::new(video_part, PlanAction::Unavailable);
+                        track.error = Some(e.to_string());
+                        track
+                    })
+                    .collect();
+            }
+        };
+        let filter = &filter;
+        let tasks = pages.iter().map(|video_part| async move {
+            if filter.reason(self, video_part).is_some() {
+                return PlannedTrack::new(video_part, PlanAction::Filtered);
+            }
+            if let Some(entry) = archive.and_then(|archive| {
+                archive.downloaded(video_part.cid, subscription.archive_scope())
+            }) {
+                let mut track = PlannedTrack::new(video_part, PlanAction::Skip);
+                track.path = Some(entry.path);
+                return track;
+            }
+            let mut track = PlannedTrack::new(video_part, PlanAction::Unavailable);
+            let audio_streams = match video_part.get_dash_audio_stream(&self.bvid, user).await {
+                Ok(audio_streams) => audio_streams,
+                Err(e) => {
+                    warn!("获取分P {} 的音频流失败: {}", video_part.page, e);
+                    track.error = Some(e.to_string());
+                    return track;
+                }
+            };
+            let Some(best_audio) =
+                DashAudioStream::select(audio_streams.as_slice(), subscription.quality)
+            else {
+                return track;
+            };
+            let (format, path) = output_path(dir, self, video_part, best_audio, subscription);
+            track.action = if path.exists() {
+                PlanAction::Overwrite
+            } else {
+                PlanAction::Download
+            };
+            track.path = Some(path);
+            track.format = Some(format);
+            track.quality = Some(best_audio.get_quality_description());
+            track.tags = Some(TrackTags::render(self, video_part, subscription));
+            track
+        });
+        future::join_all(tasks).await
+    }
+
+    /// 下载所有分P的最高音质音频
+    ///
+    /// 返回每个分P的处理结果，单个分P失败不会中断其他分P
     pub async fn download_best_quality_audios_to_file(
         &self,
         user: &User,
-        dir: &PathBuf,
+        dir: &Path,
         subscription: &Subscription,
-    ) -> Result<()> {
+        archive: Option<&Archive>,
+    ) -> Result<Vec<TrackResult>> {
         if let Some(pages) = &self.pages {
             info!("开始下载视频 {} 的 {} 个分P", self.bvid, pages.len());
-            let tasks = pages.iter().map(|video_part| {
-                let user = user;
-                let dir = dir;
-                async move {
-                    info!("处理分P {} - {}", video_part.page, video_part.part);
-                    match video_part.get_dash_audio_stream(&self.bvid, user).await {
-                        Ok(audio_streams) => {
-                            if let Some(best_audio) =
-                                DashAudioStream::get_highest_quality(audio_streams.as_slice())
-                            {
-                                // 首先下载原始音频文件
-                                let temp_dir = match tempfile::TempDir::new() {
-                                    Ok(dir) => dir,
-                                    Err(e) => {
-                                        error!("创建临时目录失败: {}", e);
-                                        return;
-                                    }
-                                };
-
-                                let temp_file_name = format!(
-                                    "{}-{}_{}.m4a",
-                                    self.title.replace("/", "_"),
-                                    video_part.page,
-                                    best_audio.get_quality_description()
-                                );
-                                let temp_file_path = temp_dir.path().join(&temp_file_name);
-
-                                info!("正在下载原始音频文件: {}", temp_file_name);
-                                if let Err(e) = user
-                                    .download_to_file(
-                                        &best_audio.base_url,
-                                        &temp_dir.path().to_path_buf(),
-                                        &temp_file_name,
-                                    )
-                                    .await
-                                {
-                                    error!("下载原始音频文件失败: {}", e);
-                                    return;
-                                }
-
-                                debug!("原始音频下载完成，开始转换和添加元数据");
-
-                                // 转换格式并添加元数据
-                                match convert_audio_with_metadata(
-                                    &temp_file_path,
-                                    dir,
-                                    self,
-                                    video_part,
-                                    best_audio,
-                                    subscription,
-                                )
-                                .await
-                                {
-                                    Ok(output_path) => {
-                                        if let Err(e) = validate_converted_file(&output_path) {
-                                            warn!("转换后的文件验证失败: {}", e);
-                                        }
-                                    }
-                                    Err(e) => {
-                                        error!("音频转换失败: {}", e);
-                                    }
-                                }
-
-                                // 清理临时文件
-                                drop(temp_dir);
-                            } else {
-                                warn!("分P {} 未找到可用的音频流", video_part.page);
-                            }
-                        }
-                        Err(e) => {
-                            warn!("获取分P {} 的音频流失败: {}", video_part.page, e);
-                        }
+            let filter = subscription.filter.compile()?;
+            if let Some(reason) = filter.video_reason(self) {
+                info!("视频 {} 被过滤条件排除: {}", self.bvid, reason);
+                return Ok(self.filtered_tracks(user));
+            }
+            // 先更新已下载分P的文件名，新分P才不会覆盖序号变化前的文件
+            let updated = match archive {
+                Some(archive) => self.update_archived_parts(dir, subscription, archive).await,
+                None => BTreeMap::new(),
+            };
+            // 封面每个视频只获取一次，失败时不嵌入封面
+            let cover = match subscription.cover {
+                true => self.fetch_cover(user).await,
+                false => None,
+            };
+            let (filter, updated, cover) = (&filter, &updated, cover.as_deref());
+            let tasks = pages.iter().map(|video_part| async move {
+                user.progress().emit(ProgressEvent::TrackStarted {
+                    bvid: self.bvid.clone(),
+                    page: video_part.page,
+                    part: video_part.part.clone(),
+                });
+                let mut track = match filter.reason(self, video_part) {
+                    Some(reason) => {
+                        debug!("分P {} 被过滤条件排除: {}", video_part.page, reason);
+                        TrackResult::new(video_part, TrackStatus::Filtered)
+                    }
+                    None => {
+                        self.download_part(user, dir, video_part, subscription, archive, cover)
+                            .await
                     }
+                };
+                match updated.get(&video_part.cid) {
+                    Some(None) if track.status == TrackStatus::Skipped => {
+                        track.status = TrackStatus::Retagged
+                    }
+                    Some(Some(error)) => track = track.failed(error),
+                    _ => {}
+                }
+                if let Some(error) = &track.error {
+                    user.progress().emit(ProgressEvent::PartFailed {
+                        bvid: self.bvid.clone(),
+                        page: video_part.page,
+                        error: error.clone(),
+                    });
                 }
+                user.progress().emit(ProgressEvent::TrackFinished {
+                    bvid: self.bvid.clone(),
+                    track: track.clone(),
+                });
+                track
             });
-            future::join_all(tasks).await;
+            let tracks = future::join_all(tasks).await;
             info!("视频 {} 下载完成", self.bvid);
-            Ok(())
+            Ok(tracks)
         } else {
             error!("视频 {} 没有分P信息", self.bvid);
             Err(BilidownError::ApiError("视频没有分P信息".to_string()))
         }
     }
+
+    /// 视频被过滤条件排除时，将全部分P记为已过滤，不请求音频流也不修改已下载的文件
+    pub fn filtered_tracks(&self, user: &User) -> Vec<TrackResult> {
+        let pages = self.pages.as_deref().unwrap_or_default();
+        pages
+            .iter()
+            .map(|video_part| {
+                user.progress().emit(ProgressEvent::TrackStarted {
+                    bvid: self.bvid.clone(),
+                    page: video_part.page,
+                    part: video_part.part.clone(),
+                });
+                let track = TrackResult::new(video_part, TrackStatus::Filtered);
+                user.progress().emit(ProgressEvent::TrackFinished {
+                    bvid: self.bvid.clone(),
+                    track: track.clone(),
+                });
+                track
+            })
+            .collect()
+    }
+
+    /// 更新下载记录中序号或标题已变化的分P，返回更新过的分P的 cid 及失败原因
+    ///
+    /// 序号变化时按新序号重命名文件并重写标签，标题变化时仅在订阅开启 `retag` 时重写标签。
+    /// 重命名分两步进行，先移到临时文件名，避免序号互换的分P互相覆盖。
+    async fn update_archived_parts(
+        &self,
+        dir: &Path,
+        subscription: &Subscription,
+        archive: &Archive,
+    ) -> BTreeMap<i64, Option<String>> {
+        let Some(pages) = &self.pages else {
+            return BTreeMap::new();
+        };
+        let archived = archive.entries_for(&self.bvid, subscription.archive_scope());
+        let new_parts = pages
+            .iter()
+            .filter(|part| !archived.iter().any(|entry| entry.cid == part.cid))
+            .count();
+        if !archived.is_empty() && new_parts > 0 {
+            info!("视频 {} 新增了 {} 个分P", self.bvid, new_parts);
+        }
+
+        let mut changed = Vec::new();
+        for part in pages {
+            let Some(entry) = archived
+                .iter()
+                .find(|entry| entry.cid == part.cid && entry.path.exists())
+            else {
+                continue;
+            };
+            let renumbered = entry.page != part.page;
+            let retitled = entry.part != part.part;
+            if renumbered {
+                info!(
+                    "分P {} ({}) 的序号已变为 {}",
+                    entry.page, entry.part, part.page
+                );
+            } else if retitled && subscription.retag {
+                info!(
+                    "分P {} 的标题已由 {} 变为 {}",
+                    part.page, entry.part, part.part
+                );
+            } else {
+                if retitled {
+                    info!(
+                        "分P {} 的标题已由 {} 变为 {}，未开启 retag，保留原标签",
+                        part.page, entry.part, part.part
+                    );
+                }
+                continue;
+            }
+            changed.push((part, entry.clone(), renumbered));
+        }
+
+        let mut updated = BTreeMap::new();
+        let mut staged = Vec::new();
+        for (part, entry, renumbered) in changed {
+            let source = if renumbered {
+                let temp = entry.path.with_file_name(format!(".{}.renumber", part.cid));
+                if let Err(e) = std::fs::rename(&entry.path, &temp) {
+                    warn!("重命名分P {} 失败: {}", part.page, e);
+                    updated.insert(part.cid, Some(e.to_string()));
+                    continue;
+                }
+                rename_lyrics(&entry.path, &temp);
+                temp
+            } else {
+                entry.path.clone()
+            };
+            staged.push((part, entry, source));
+        }
+        for (part, entry, source) in staged {
+            let target = if source == entry.path {
+                source
+            } else {
+                let target = renumbered_path(&entry.path, dir, self, part, subscription);
+                if let Some(parent) = target.parent()
+                    && let Err(e) = std::fs::create_dir_all(parent)
+                {
+                    warn!("创建目录 {:?} 失败: {}", parent, e);
+                }
+                if let Err(e) = std::fs::rename(&source, &target) {
+                    warn!("重命名分P {} 失败: {}", part.page, e);
+                    restore_renumbered(&source, &entry, archive, subscription).await;
+                    updated.insert(part.cid, Some(e.to_string()));
+                    continue;
+                }
+                rename_lyrics(&source, &target);
+                target
+            };
+            let result = match add_metadata_to_file(&target, self, part, subscription) {
+                Ok(_) => {
+                    archive
+                        .insert(ArchiveEntry {
+                            page: part.page,
+                            part: part.part.clone(),
+                            title: self.title.clone(),
+                            path: target,
+                            ..entry
+                        })
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = &result {
+                warn!("更新分P {} 的标签失败: {}", part.page, e);
+            }
+            updated.insert(part.cid, result.err().map(|e| e.to_string()));
+        }
+        updated
+    }
+
+    async fn download_part(
+        &self,
+        user: &User,
+        dir: &Path,
+        video_part: &VideoPart,
+        subscription: &Subscription,
+        archive: Option<&Archive>,
+        cover: Option<&[u8]>,
+    ) -> TrackResult {
+        info!("处理分P {} - {}", video_part.page, video_part.part);
+        let mut track = TrackResult::new(video_part, TrackStatus::Downloaded);
+        if let Some(entry) = archive
+            .and_then(|archive| archive.downloaded(video_part.cid, subscription.archive_scope()))
+        {
+            info!("分P {} 已在下载记录中，跳过", video_part.page);
+            track.status = TrackStatus::Skipped;
+            track.size = std::fs::metadata(&entry.path).ok().map(|m| m.len());
+            track.path = Some(entry.path);
+            return track;
+        }
+
+        let audio_streams = match video_part.get_audio_streams(&self.bvid, user).await {
+            Ok(audio_streams) => audio_streams,
+            Err(e) => {
+                warn!("获取分P {} 的音频流失败: {}", video_part.page, e);
+                return track.failed(e);
+            }
+        };
+        let Some(best_audio) =
+            DashAudioStream::select(audio_streams.streams.as_slice(), subscription.quality)
+        else {
+            warn!("分P {} 未找到可用的音频流", video_part.page);
+            track.status = TrackStatus::Unavailable;
+            return track;
+        };
+        track.quality = Some(best_audio.get_quality_description());
+        track.format = Some(output_format(best_audio, subscription));
+        track.limited =
+            user.is_anonymous() && audio_streams.limited_by_login(best_audio, subscription.quality);
+        user.progress().emit(ProgressEvent::StreamSelected {
+            bvid: self.bvid.clone(),
+            page: video_part.page,
+            quality: best_audio.get_quality_description(),
+            format: output_format(best_audio, subscription),
+            limited: track.limited,
+        });
+
+        // 原始音频先下载到输出目录中的隐藏文件，中断后再次运行时从已下载部分继续
+        let part_file_name = format!(
+            ".{}-{}-{}.m4a.part",
+            self.bvid, video_part.cid, best_audio.id
+        );
+        let part_file_path = dir.join(&part_file_name);
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            error!("创建输出目录失败: {}", e);
+            return track.failed(e);
+        }
+
+        info!(
+            "正在下载分P {} 的原始音频 ({})",
+            video_part.page,
+            best_audio.get_quality_description()
+        );
+        if let Err(e) = user
+            .download_to_file(
+                &best_audio.base_url,
+                dir,
+                &part_file_name,
+                |downloaded, total| {
+                    user.progress().emit(ProgressEvent::DownloadProgress {
+                        bvid: self.bvid.clone(),
+                        page: video_part.page,
+                        downloaded,
+                        total,
+                    })
+                },
+            )
+            .await
+        {
+            error!("下载原始音频文件失败: {}", e);
+            return track.failed(e);
+        }
+
+        debug!("原始音频下载完成，开始转换和添加元数据");
+
+        // 转换格式并添加元数据
+        user.progress().emit(ProgressEvent::Converting {
+            bvid: self.bvid.clone(),
+            page: video_part.page,
+        });
+        let permit = user.limiter().ffmpeg().await;
+        let converted = convert_audio(
+            &part_file_path,
+            dir,
+            self,
+            video_part,
+            best_audio,
+            subscription,
+        )
+        .await;
+        drop(permit);
+        let output_path = match converted {
+            Ok(output_path) => output_path,
+            Err(e) => {
+                error!("音频转换失败: {}", e);
+                // 原始文件可能已损坏，下次重新下载
+                let _ = std::fs::remove_file(&part_file_path);
+                return track.failed(e);
+            }
+        };
+        user.progress().emit(ProgressEvent::Converted {
+            bvid: self.bvid.clone(),
+            page: video_part.page,
+            path: output_path.clone(),
+        });
+        match add_metadata_to_file(&output_path, self, video_part, subscription) {
+            Ok(tags) => user.progress().emit(ProgressEvent::Tagged {
+                bvid: self.bvid.clone(),
+                page: video_part.page,
+                tags,
+            }),
+            Err(e) => {
+                error!("写入元数据失败: {}", e);
+                return track.failed(e);
+            }
+        }
+        self.embed_extras(user, video_part, subscription, &output_path, cover)
+            .await;
+        // 清理原始音频文件
+        if let Err(e) = std::fs::remove_file(&part_file_path) {
+            warn!("删除原始音频文件失败: {}", e);
+        }
+
+        track.size = std::fs::metadata(&output_path).ok().map(|m| m.len());
+        track.path = Some(output_path.clone());
+        if let Err(e) = validate_converted_file(&output_path) {
+            warn!("转换后的文件验证失败: {}", e);
+            return track.failed(e);
+        }
+        if let Some(archive) = archive {
+            let entry = ArchiveEntry {
+                bvid: self.bvid.clone(),
+                cid: video_part.cid,
+                page: video_part.page,
+                title: self.title.clone(),
+                part: video_part.part.clone(),
+                path: output_path,
+                downloaded_at: chrono::Local::now(),
+                scope: subscription.output_dir.clone(),
+            };
+            if let Err(e) = archive.insert(entry).await {
+                warn!("写入下载记录失败: {}", e);
+            }
+        }
+        track
+    }
+
+    /// 获取视频封面，失败时只记录警告
+    pub async fn fetch_cover(&self, user: &User) -> Option<Vec<u8>> {
+        match user.get_bytes(&self.pic).await {
+            Ok(cover) => Some(cover),
+            Err(e) => {
+                warn!("获取视频 {} 的封面失败: {}", self.bvid, e);
+                None
+            }
+        }
+    }
+
+    /// 按订阅设置嵌入封面与歌词，并写入 `.lrc` 歌词文件，失败时只记录警告
+    async fn embed_extras(
+        &self,
+        user: &User,
+        video_part: &VideoPart,
+        subscription: &Subscription,
+        output_path: &Path,
+        cover: Option<&[u8]>,
+    ) {
+        let lyrics = match subscription.lyrics {
+            true => match lyrics::fetch(user, &self.bvid, video_part.cid).await {
+                Ok(lyrics) => lyrics,
+                Err(e) => {
+                    warn!("获取分P {} 的字幕失败: {}", video_part.page, e);
+                    None
+                }
+            },
+            false => None,
+        };
+        if let Some(lyrics) = &lyrics
+            && let Err(e) = std::fs::write(output_path.with_extension("lrc"), lyrics)
+        {
+            warn!("写入分P {} 的歌词文件失败: {}", video_part.page, e);
+        }
+        if let Err(e) = embed_cover_and_lyrics(output_path, cover, lyrics.as_deref()) {
+            warn!("嵌入分P {} 的封面与歌词失败: {}", video_part.page, e);
+        }
+    }
+}
+
+/// 序号调整的第二步失败时将临时文件移回原位置
+///
+/// 原位置已被其他分P占用时删除临时文件与下载记录，下次运行时重新下载
+async fn restore_renumbered(
+    temp: &Path,
+    entry: &ArchiveEntry,
+    archive: &Archive,
+    subscription: &Subscription,
+) {
+    if !entry.path.exists() && std::fs::rename(temp, &entry.path).is_ok() {
+        rename_lyrics(temp, &entry.path);
+        return;
+    }
+    warn!(
+        "无法恢复分P {} 的原文件 {:?}，将在下次运行时重新下载",
+        entry.page, entry.path
+    );
+    for path in [temp.to_path_buf(), temp.with_extension("lrc")] {
+        if path.exists()
+            && let Err(e) = std::fs::remove_file(&path)
+        {
+            warn!("删除临时文件 {:?} 失败: {}", path, e);
+        }
+    }
+    if let Err(e) = archive
+        .remove(entry.cid, subscription.archive_scope())
+        .await
+    {
+        warn!("删除下载记录失败: {}", e);
+    }
+}
+
+/// 音频文件重命名后同步重命名对应的 `.lrc` 歌词文件
+fn rename_lyrics(from: &Path, to: &Path) {
+    let lyrics = from.with_extension("lrc");
+    if lyrics.exists()
+        && let Err(e) = std::fs::rename(&lyrics, to.with_extension("lrc"))
+    {
+        warn!("重命名歌词文件 {:?} 失败: {}", lyrics, e);
+    }
 }
@@ -1,8 +1,15 @@
-use reqwest::{RequestBuilder, Url, header::USER_AGENT};
-use serde::Deserialize;
-use std::time::{SystemTime, UNIX_EPOCH};
-
-use crate::error::Result;
+use chrono::{DateTime, Local};
+use reqwest::RequestBuilder;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::{BilidownError, Result},
+    user::User,
+};
 
 const MIXIN_KEY_ENC_TAB: [usize; 64] = [
     46, 47, 18, 2, 53, 8, 23, 32, 15, 50, 10, 31, 58, 3, 45, 35, 27, 43, 5, 49, 33, 9, 42, 19, 29,
@@ -89,22 +96,54 @@ fn _encode_wbi(
     params
 }
 
-pub async fn get_wbi_keys() -> Result<(String, String)> {
-    let client = reqwest::Client::new();
-    let ResWbi { data:Data{wbi_img} } = client
-    .get("https://api.bilibili.com/x/web-interface/nav")
-    .header(USER_AGENT,"Mozilla/5.0 (Windows NT 10.0; WOW64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Safari/537.36")
-    .header("Referer","https://www.bilibili.com/")
-     // SESSDATA=xxxxx
-    .header("Cookie", "SESSDATA=xxxxx")
-    .send()
-    .await?
-    .json::<ResWbi>()
-    .await?;
-    Ok((
-        take_filename(wbi_img.img_url).unwrap(),
-        take_filename(wbi_img.sub_url).unwrap(),
-    ))
+/// 缓存的 WBI 密钥，B 站每日轮换一次
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WbiKeys {
+    pub img_key: String,
+    pub sub_key: String,
+    pub fetched_at: DateTime<Local>,
+}
+
+impl WbiKeys {
+    /// 密钥是否已过期（非当天获取）
+    pub fn is_expired(&self) -> bool {
+        self.fetched_at.date_naive() != Local::now().date_naive()
+    }
+
+    /// 从缓存文件读取密钥，文件不存在或格式错误时返回 `None`
+    pub async fn load(path: &Path) -> Option<Self> {
+        let contents = tokio::fs::read_to_string(path).await.ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, serde_json::to_string_pretty(self)?).await?;
+        Ok(())
+    }
+}
+
+pub async fn get_wbi_keys(user: &User) -> Result<WbiKeys> {
+    let ResWbi {
+        data: Data { wbi_img },
+    } = user
+        .send(user.get(&user.api_url("/x/web-interface/nav")))
+        .await?
+        .json::<ResWbi>()
+        .await?;
+    match (
+        take_filename(wbi_img.img_url),
+        take_filename(wbi_img.sub_url),
+    ) {
+        (Some(img_key), Some(sub_key)) => Ok(WbiKeys {
+            img_key,
+            sub_key,
+            fetched_at: Local::now(),
+        }),
+        _ => Err(BilidownError::ApiError("WBI密钥格式无效".to_string())),
+    }
 }
 
 fn take_filename(url: String) -> Option<String> {
@@ -113,6 +152,14 @@ fn take_filename(url: String) -> Option<String> {
         .map(|(s, _)| s.to_string())
 }
 
+/// 为请求地址中的全部查询参数加上 wts 与 w_rid 签名
+pub(crate) fn sign(request: &mut reqwest::Request, img_key: &str, sub_key: &str) {
+    let url = request.url_mut();
+    let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    let signed = encode_wbi(params, (img_key.to_string(), sub_key.to_string()));
+    url.query_pairs_mut().clear().extend_pairs(&signed);
+}
+
 pub trait WbiSendExt {
     fn wbi_send(
         self,
@@ -123,32 +170,17 @@ pub trait WbiSendExt {
 }
 
 impl WbiSendExt for RequestBuilder {
-    fn wbi_send(
+    async fn wbi_send(
         self,
         client: &reqwest::Client,
         img_key: &str,
         sub_key: &str,
-    ) -> impl std::future::Future<Output = std::result::Result<reqwest::Response, reqwest::Error>> + Send
-    {
-        async move {
-            let mut request = self.build()?;
-            let url = request.url_mut();
-
-            let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
-            let signed = encode_wbi(params, (img_key.to_string(), sub_key.to_string()));
-
-            let base = format!(
-                "{}://{}{}",
-                url.scheme(),
-                url.host_str().unwrap_or(""),
-                url.path()
-            );
-            let new_url = Url::parse_with_params(&base, &signed).unwrap();
-            *url = new_url;
-
-            // 使用传入的 client 发送！
-            client.execute(request).await
-        }
+    ) -> std::result::Result<reqwest::Response, reqwest::Error> {
+        let mut request = self.build()?;
+        sign(&mut request, img_key, sub_key);
+
+        // 使用传入的 client 发送！
+        client.execute(request).await
     }
 }
 
@@ -167,6 +199,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_wbi_keys_expire_next_day() {
+        let mut keys = WbiKeys {
+            img_key: "7cd084941338484aae1ad9425b84077c".to_string(),
+            sub_key: "4932caff0ff746eab6f01bf08b70ac45".to_string(),
+            fetched_at: Local::now(),
+        };
+        assert!(!keys.is_expired());
+        keys.fetched_at = Local::now() - chrono::Duration::days(1);
+        assert!(keys.is_expired());
+    }
+
     #[test]
     fn test_get_mixin_key() {
         let concat_key =
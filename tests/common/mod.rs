@@ -0,0 +1,275 @@
+//! A local stand-in for the Bilibili API used by the integration tests
+//!
+//! Responses are recorded JSON files under `tests/fixtures`. Audio streams are
+//! served as silent MP3 frames generated on the fly.
+
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+use axum::{
+    Router,
+    extract::{Path, Query, State},
+    http::{
+        HeaderMap, StatusCode,
+        header::{CONTENT_RANGE, CONTENT_TYPE, COOKIE, RANGE, SET_COOKIE},
+    },
+    response::{AppendHeaders, IntoResponse},
+    routing::{get, post},
+};
+use ov_bilidown::api::client::EndpointConfig;
+use tokio::net::TcpListener;
+
+pub struct FakeBilibili {
+    pub addr: SocketAddr,
+    pub state: Arc<FakeState>,
+}
+
+/// 用例可调整的服务器行为与请求计数
+#[derive(Default)]
+pub struct FakeState {
+    /// 为真时 GenWebTicket 返回错误
+    pub ticket_unavailable: AtomicBool,
+    /// GenWebTicket 收到的请求数
+    pub ticket_requests: AtomicUsize,
+    /// 接下来的多少个视频信息请求以 -352 拒绝
+    pub risk_control: AtomicUsize,
+    /// 为真时所有 SESSDATA 都视为已过期
+    pub sessions_expired: AtomicBool,
+    /// 携带 SESSDATA 的视频信息请求数
+    pub logged_in_views: AtomicUsize,
+}
+
+impl FakeBilibili {
+    pub async fn start() -> Self {
+        let state = Arc::new(FakeState::default());
+        let app = Router::new()
+            .route("/x/web-interface/nav", get(nav))
+            .route("/x/frontend/finger/spi", get(|| fixture("spi.json")))
+            .route(
+                "/bapis/bilibili.api.ticket.v1.Ticket/GenWebTicket",
+                post(ticket),
+            )
+            .route("/x/web-interface/view", get(view))
+            .route("/x/player/playurl", get(playurl))
+            .route("/x/player/wbi/v2", get(player))
+            .route("/bfs/subtitle/{name}", get(subtitle))
+            .route("/bfs/archive/{name}", get(cover))
+            .route(
+                "/x/passport-login/web/qrcode/generate",
+                get(|| fixture("qrcode_generate.json")),
+            )
+            .route("/x/passport-login/web/qrcode/poll", get(qrcode_poll))
+            .route("/upgcxcode/{*path}", get(audio))
+            .with_state(state.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Self { addr, state }
+    }
+
+    /// 将主站、登录与 CDN 地址全部指向本地服务器
+    pub fn endpoints(&self) -> EndpointConfig {
+        let base = format!("http://{}", self.addr);
+        EndpointConfig {
+            api: base.clone(),
+            passport: base.clone(),
+            cdn: Some(base),
+        }
+    }
+}
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
+
+async fn fixture(name: &str) -> impl IntoResponse {
+    let body = tokio::fs::read_to_string(fixture_path(name)).await.unwrap();
+    ([(CONTENT_TYPE, "application/json")], body)
+}
+
+/// 与真实接口一致：携带 SESSDATA 时视为已登录
+async fn nav(State(state): State<Arc<FakeState>>, headers: HeaderMap) -> impl IntoResponse {
+    let expired = state.sessions_expired.load(Ordering::SeqCst);
+    fixture(if logged_in(&headers) && !expired {
+        "nav.json"
+    } else {
+        "nav_guest.json"
+    })
+    .await
+}
+
+fn logged_in(headers: &HeaderMap) -> bool {
+    headers
+        .get(COOKIE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|cookie| cookie.contains("SESSDATA="))
+}
+
+async fn ticket(State(state): State<Arc<FakeState>>) -> impl IntoResponse {
+    state.ticket_requests.fetch_add(1, Ordering::SeqCst);
+    if state.ticket_unavailable.load(Ordering::SeqCst) {
+        return (
+            [(CONTENT_TYPE, "application/json")],
+            r#"{"code":-400,"message":"请求错误","ttl":1}"#,
+        )
+            .into_response();
+    }
+    fixture("ticket.json").await.into_response()
+}
+
+/// 缺少 WBI 签名时返回 -403，与真实接口的风控行为一致
+fn is_signed(params: &HashMap<String, String>) -> bool {
+    params.contains_key("w_rid") && params.contains_key("wts")
+}
+
+async fn view(
+    State(state): State<Arc<FakeState>>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if !is_signed(&params) {
+        return fixture("wbi_error.json").await.into_response();
+    }
+    if logged_in(&headers) {
+        state.logged_in_views.fetch_add(1, Ordering::SeqCst);
+    }
+    let rejected = state
+        .risk_control
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+        .is_ok();
+    if rejected {
+        return (
+            [(CONTENT_TYPE, "application/json")],
+            r#"{"code":-352,"message":"风控校验失败","ttl":1}"#,
+        )
+            .into_response();
+    }
+    let name = format!("view_{}.json", params["bvid"]);
+    if fixture_path(&name).exists() {
+        fixture(&name).await.into_response()
+    } else {
+        fixture("view_deleted.json").await.into_response()
+    }
+}
+
+/// 未登录时优先返回 `playurl_{cid}_guest.json`，其中不含需要登录的音轨
+async fn playurl(
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if !is_signed(&params) {
+        return fixture("wbi_error.json").await.into_response();
+    }
+    let guest = format!("playurl_{}_guest.json", params["cid"]);
+    let name = format!("playurl_{}.json", params["cid"]);
+    if !logged_in(&headers) && fixture_path(&guest).exists() {
+        fixture(&guest).await.into_response()
+    } else if fixture_path(&name).exists() {
+        fixture(&name).await.into_response()
+    } else {
+        fixture("playurl_missing.json").await.into_response()
+    }
+}
+
+async fn player(Query(params): Query<HashMap<String, String>>) -> impl IntoResponse {
+    if !is_signed(&params) {
+        return fixture("wbi_error.json").await.into_response();
+    }
+    let name = format!("player_{}.json", params["cid"]);
+    if fixture_path(&name).exists() {
+        fixture(&name).await.into_response()
+    } else {
+        fixture("player_empty.json").await.into_response()
+    }
+}
+
+async fn subtitle(Path(name): Path<String>) -> impl IntoResponse {
+    let name = format!("subtitle_{}", name);
+    if fixture_path(&name).exists() {
+        fixture(&name).await.into_response()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+/// 所有封面都返回同一张 1x1 的 PNG 图片
+async fn cover() -> impl IntoResponse {
+    ([(CONTENT_TYPE, "image/png")], sample_png())
+}
+
+async fn qrcode_poll() -> impl IntoResponse {
+    (
+        AppendHeaders([
+            (
+                SET_COOKIE,
+                "SESSDATA=fake-sessdata; Path=/; Domain=bilibili.com",
+            ),
+            (
+                SET_COOKIE,
+                "bili_jct=fake-csrf; Path=/; Domain=bilibili.com",
+            ),
+        ]),
+        fixture("qrcode_poll.json").await,
+    )
+}
+
+/// 与 CDN 一致：支持 `Range: bytes=N-` 续传请求
+async fn audio(headers: HeaderMap) -> impl IntoResponse {
+    let body = sample_mp3(200);
+    let start = headers
+        .get(RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.strip_suffix('-'))
+        .and_then(|start| start.parse::<usize>().ok());
+    match start {
+        None => (StatusCode::OK, HeaderMap::new(), body),
+        Some(start) if start >= body.len() => (
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            HeaderMap::new(),
+            Vec::new(),
+        ),
+        Some(start) => {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, body.len() - 1, body.len())
+                    .parse()
+                    .unwrap(),
+            );
+            (StatusCode::PARTIAL_CONTENT, headers, body[start..].to_vec())
+        }
+    }
+}
+
+/// 生成指定帧数的静音 MP3（MPEG-1 Layer III, 128kbps, 44.1kHz, 单声道）
+pub fn sample_mp3(frames: usize) -> Vec<u8> {
+    const FRAME_LEN: usize = 417;
+    let mut frame = vec![0u8; FRAME_LEN];
+    frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0xC4]);
+    frame.repeat(frames)
+}
+
+/// 1x1 的透明 PNG 图片
+pub fn sample_png() -> Vec<u8> {
+    vec![
+        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0x1F,
+        0x15, 0xC4, 0x89, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9C, 0x63, 0x00,
+        0x01, 0x00, 0x00, 0x05, 0x00, 0x01, 0x0D, 0x0A, 0x2D, 0xB4, 0x00, 0x00, 0x00, 0x00, 0x49,
+        0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+    ]
+}
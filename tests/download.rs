@@ -0,0 +1,839 @@
+//! End-to-end tests against a local fake Bilibili server
+
+mod common;
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+};
+
+use common::FakeBilibili;
+use lofty::{picture::PictureType, prelude::*, tag::ItemKey};
+use ov_bilidown::{
+    BilidownError,
+    archive::{Archive, ArchiveEntry},
+    config::{AppConfig, Defaults, Overrides},
+    converter::{self, AudioFormat},
+    download::AudioQuality,
+    filter::ContentFilter,
+    limit::LimitConfig,
+    lyrics,
+    mirror::RemovedPolicy,
+    playlist::PlaylistFormat,
+    progress::ProgressEvent,
+    report::{PlanAction, RemovedAction, RunSummary, TrackStatus},
+    subscription::Subscription,
+    user::User,
+};
+
+fn subscription(bvid: &str) -> Subscription {
+    Subscription {
+        bvid: bvid.to_string(),
+        title: Some("{part_title}".to_string()),
+        artist: Some("{artist}".to_string()),
+        album: Some("{title}".to_string()),
+        ..Default::default()
+    }
+}
+
+async fn logged_in_user(server: &FakeBilibili, dir: &Path) -> User {
+    let cookie_file = dir.join("cookies.txt");
+    std::fs::write(&cookie_file, "SESSDATA=fake-sessdata\nbili_jct=fake-csrf").unwrap();
+    let mut user = User::new_from_file(&cookie_file, server.endpoints(), None)
+        .await
+        .unwrap();
+    user.set_limits(&unlimited());
+    user
+}
+
+/// 本地服务器无需限制请求频率
+fn unlimited() -> LimitConfig {
+    LimitConfig {
+        api_rate: 0.0,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn login_with_qrcode_and_reload_cookies() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let cookie_file = dir.path().join("cookies.txt");
+
+    let user = User::new(server.endpoints(), None).await.unwrap();
+    assert!(!user.is_anonymous());
+    user.save_to_file(&cookie_file).unwrap();
+
+    let saved = std::fs::read_to_string(&cookie_file).unwrap();
+    assert!(saved.contains("SESSDATA=fake-sessdata"));
+    assert!(saved.contains("bili_jct=fake-csrf"));
+    assert!(
+        User::new_from_file(&cookie_file, server.endpoints(), None)
+            .await
+            .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn expired_session_falls_back_without_stale_cookies() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let mut user = logged_in_user(&server, dir.path()).await;
+    let config = AppConfig {
+        output_dir: dir.path().join("out"),
+        cookie_file: dir.path().join("missing-cookies.txt"),
+        subscription_file: dir.path().join("subscriptions.toml"),
+        archive_file: dir.path().join("archive.json"),
+        wbi_cache_file: dir.path().join("wbi.json"),
+        identity_file: dir.path().join("identity.json"),
+        daemon_state_file: dir.path().join("daemon.json"),
+        queue_file: dir.path().join("queue.json"),
+        anonymous: false,
+        endpoints: server.endpoints(),
+        limits: unlimited(),
+        daemon: Default::default(),
+        server: Default::default(),
+        defaults: Defaults::default(),
+        overrides: Overrides::default(),
+        subscriptions: Vec::new(),
+        included_files: Vec::new(),
+    };
+    server.state.sessions_expired.store(true, Ordering::SeqCst);
+
+    user.refresh_session(&config).await;
+    assert!(user.is_anonymous());
+    ov_bilidown::VideoBasicInfo::new_from_bvid(&user, "BV1fk4y1x7Aa")
+        .await
+        .unwrap();
+    assert_eq!(server.state.logged_in_views.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn invalid_cookie_file_is_rejected() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let cookie_file = dir.path().join("cookies.txt");
+    std::fs::write(&cookie_file, "buvid3=only-a-device-id").unwrap();
+
+    let result = User::new_from_file(&cookie_file, server.endpoints(), None).await;
+    assert!(matches!(result, Err(BilidownError::LoginError(_))));
+}
+
+#[tokio::test]
+#[ignore = "需要系统中安装 ffmpeg，使用 cargo test -- --ignored 运行"]
+async fn download_writes_tagged_files_and_skips_parts_without_audio() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let output_dir = dir.path().join("out");
+
+    let subscription = Subscription {
+        playlist: vec![PlaylistFormat::M3u8],
+        ..subscription("BV1fk4y1x7Aa")
+    };
+    let report = subscription
+        .download(&user, &output_dir, None)
+        .await
+        .unwrap();
+    let statuses: Vec<_> = report.tracks.iter().map(|track| track.status).collect();
+    assert_eq!(
+        statuses,
+        [
+            TrackStatus::Downloaded,
+            TrackStatus::Downloaded,
+            TrackStatus::Unavailable
+        ]
+    );
+    assert!(report.tracks[0].size.is_some_and(|size| size > 0));
+
+    let video_dir = output_dir.join("BV1fk4y1x7Aa");
+    for (page, part) in [(1, "第一首"), (2, "第二首")] {
+        let path = video_dir.join(format!("测试合集-P{}.mp3", page));
+        let tagged = lofty::read_from_path(&path).unwrap();
+        let tag = tagged.primary_tag().unwrap();
+        assert_eq!(tag.title().as_deref(), Some(part));
+        assert_eq!(tag.artist().as_deref(), Some("测试UP主"));
+        assert_eq!(tag.album().as_deref(), Some("测试合集"));
+        assert_eq!(tag.track(), Some(page));
+    }
+    // P3 没有可用的音频流，应被跳过
+    assert!(!video_dir.join("测试合集-P3.mp3").exists());
+
+    // 播放列表按分P顺序列出已下载的音轨，使用相对路径
+    let playlist = std::fs::read_to_string(video_dir.join("测试合集.m3u8")).unwrap();
+    let locations: Vec<_> = playlist
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    assert_eq!(locations, ["测试合集-P1.mp3", "测试合集-P2.mp3"]);
+    assert!(playlist.contains("#EXTINF:5,测试UP主 - 第一首\n"));
+}
+
+#[tokio::test]
+async fn info_only_does_not_download() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let output_dir = dir.path().join("out");
+
+    let report = subscription("BV1fk4y1x7Aa").info(&user).await.unwrap();
+
+    assert!(!output_dir.exists());
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["video"]["title"], "测试合集");
+    let parts = json["parts"].as_array().unwrap();
+    assert_eq!(parts.len(), 3);
+    assert_eq!(parts[0]["streams"][0]["quality"], "192K");
+    assert_eq!(parts[0]["streams"][0]["requires_login"], true);
+    assert_eq!(parts[1]["streams"][0]["requires_login"], false);
+    assert!(parts[2]["streams"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn deleted_video_returns_api_error() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let output_dir = dir.path().join("out");
+
+    let result = subscription("BV1xx411c7Xd")
+        .download(&user, &output_dir, None)
+        .await;
+
+    match result {
+        Err(BilidownError::ApiError(message)) => assert!(message.contains("啥都木有")),
+        other => panic!("expected ApiError, got {:?}", other),
+    }
+    assert!(!output_dir.exists());
+}
+
+#[tokio::test]
+async fn video_without_audio_downloads_nothing() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let output_dir = dir.path().join("out");
+
+    subscription("BV1nA4y1x7Ab")
+        .download(&user, &output_dir, None)
+        .await
+        .unwrap();
+
+    let video_dir = output_dir.join("BV1nA4y1x7Ab");
+    assert_eq!(std::fs::read_dir(video_dir).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn wbi_retry_releases_permit_before_refreshing_keys() {
+    let server = FakeBilibili::start().await;
+    server.state.risk_control.store(1, Ordering::SeqCst);
+    let dir = tempfile::tempdir().unwrap();
+    let mut user = logged_in_user(&server, dir.path()).await;
+    user.set_limits(&LimitConfig {
+        api_concurrency: 1,
+        ..unlimited()
+    });
+
+    let video = tokio::time::timeout(
+        std::time::Duration::from_secs(10),
+        ov_bilidown::VideoBasicInfo::new_from_bvid(&user, "BV1fk4y1x7Aa"),
+    )
+    .await
+    .expect("刷新 WBI 密钥时不应等待自身持有的许可")
+    .unwrap();
+    assert_eq!(video.title, "测试合集");
+    assert_eq!(server.state.risk_control.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn failed_ticket_renewal_backs_off() {
+    let server = FakeBilibili::start().await;
+    server
+        .state
+        .ticket_unavailable
+        .store(true, Ordering::SeqCst);
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+
+    for _ in 0..3 {
+        ov_bilidown::VideoBasicInfo::new_from_bvid(&user, "BV1fk4y1x7Aa")
+            .await
+            .unwrap();
+    }
+
+    // 续期失败后在重试间隔内不再为每个签名请求重复申请
+    assert_eq!(server.state.ticket_requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn anonymous_user_reports_limited_tracks() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let mut user = User::new_anonymous(server.endpoints(), None);
+    user.set_limits(&unlimited());
+    let video = ov_bilidown::VideoBasicInfo::new_from_bvid(&user, "BV1fk4y1x7Aa")
+        .await
+        .unwrap();
+
+    let tracks = video
+        .download_best_quality_audios_to_file(
+            &user,
+            dir.path(),
+            &subscription("BV1fk4y1x7Aa"),
+            None,
+        )
+        .await
+        .unwrap();
+
+    // 游客请求 P1 时接口只返回 132K 并隐藏了无损音轨；P2 本身最高只有 132K，P3 没有音频
+    let limited: Vec<_> = tracks.iter().filter(|track| track.limited).collect();
+    assert_eq!(limited.len(), 1);
+    assert_eq!(limited[0].page, 1);
+    assert_eq!(limited[0].quality.as_deref(), Some("132K (132kbps)"));
+}
+
+#[tokio::test]
+async fn archived_parts_are_skipped() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let output_dir = dir.path().join("out");
+    let archive = Archive::load(&dir.path().join("archive.json")).unwrap();
+    for (cid, page) in [(1001, 1), (1002, 2)] {
+        archived_file(&archive, dir.path(), "BV1fk4y1x7Aa", cid, page).await;
+    }
+
+    let report = subscription("BV1fk4y1x7Aa")
+        .download(&user, &output_dir, Some(&archive))
+        .await
+        .unwrap();
+    assert_eq!(report.count(TrackStatus::Skipped), 2);
+
+    let video_dir = output_dir.join("BV1fk4y1x7Aa");
+    assert_eq!(std::fs::read_dir(video_dir).unwrap().count(), 0);
+    assert_eq!(archive.entries().len(), 2);
+    let reloaded = Archive::load(archive.path()).unwrap();
+    assert!(reloaded.downloaded(1001, None).is_some());
+    assert!(reloaded.downloaded(1003, None).is_none());
+}
+
+/// 写入一个可读取标签的输出文件并记录到下载记录中
+async fn archived_file(archive: &Archive, dir: &Path, bvid: &str, cid: i64, page: i32) -> PathBuf {
+    let path = dir.join(format!("{}-P{}.mp3", bvid, page));
+    std::fs::write(&path, common::sample_mp3(20)).unwrap();
+    archive
+        .insert(ArchiveEntry {
+            bvid: bvid.to_string(),
+            cid,
+            page,
+            title: "测试".to_string(),
+            part: format!("P{}", page),
+            path: path.clone(),
+            downloaded_at: chrono::Local::now(),
+            scope: None,
+        })
+        .await
+        .unwrap();
+    path
+}
+
+#[tokio::test]
+async fn removed_parts_and_deleted_videos_follow_policy() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let output_dir = dir.path().join("out");
+    let archive = Archive::load(&dir.path().join("archive.json")).unwrap();
+
+    // 视频中已不存在 cid 9999 的分P
+    let gone = archived_file(&archive, dir.path(), "BV1nA4y1x7Ab", 9999, 2).await;
+    let report = Subscription {
+        on_removed: RemovedPolicy::Delete,
+        ..subscription("BV1nA4y1x7Ab")
+    }
+    .download(&user, &output_dir, Some(&archive))
+    .await
+    .unwrap();
+    assert_eq!(report.removed.len(), 1);
+    assert_eq!(report.removed[0].action, RemovedAction::Deleted);
+    assert!(!gone.exists());
+    assert!(archive.get(9999, None).is_none());
+
+    // 整个视频失效时，移动其全部分P
+    let kept = archived_file(&archive, dir.path(), "BV1xx411c7Xd", 8888, 1).await;
+    let report = Subscription {
+        on_removed: RemovedPolicy::Move,
+        ..subscription("BV1xx411c7Xd")
+    }
+    .download(&user, &output_dir, Some(&archive))
+    .await
+    .unwrap();
+    assert!(report.error.is_none());
+    assert_eq!(report.removed[0].action, RemovedAction::Moved);
+    let target = output_dir.join("removed/BV1xx411c7Xd/BV1xx411c7Xd-P1.mp3");
+    assert_eq!(
+        report.removed[0].moved_to.as_deref(),
+        Some(target.as_path())
+    );
+    assert!(target.exists() && !kept.exists());
+    assert!(archive.entries_for("BV1xx411c7Xd", None).is_empty());
+
+    let mut summary = RunSummary::default();
+    summary.add_download(&report);
+    assert_eq!(summary.removed, 1);
+    assert!(!summary.has_failures());
+}
+
+#[tokio::test]
+async fn hidden_videos_and_missing_pages_keep_archived_files() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let output_dir = dir.path().join("out");
+    let archive = Archive::load(&dir.path().join("archive.json")).unwrap();
+
+    // 稿件不可见可能只是暂时隐藏，不处理已下载的分P
+    let hidden = archived_file(&archive, dir.path(), "BV1hH411c7Xe", 7777, 1).await;
+    // 没有分P信息时无法判断哪些分P已删除
+    let unknown = archived_file(&archive, dir.path(), "BV1pN411c7Xf", 6666, 1).await;
+    for bvid in ["BV1hH411c7Xe", "BV1pN411c7Xf"] {
+        let result = Subscription {
+            on_removed: RemovedPolicy::Delete,
+            ..subscription(bvid)
+        }
+        .download(&user, &output_dir, Some(&archive))
+        .await;
+        assert!(
+            matches!(result, Err(BilidownError::ApiError(_))),
+            "{}: {:?}",
+            bvid,
+            result
+        );
+    }
+    assert!(hidden.exists() && unknown.exists());
+    assert!(archive.get(7777, None).is_some() && archive.get(6666, None).is_some());
+    assert!(!output_dir.exists());
+}
+
+#[tokio::test]
+async fn renumbered_and_retitled_parts_are_retagged() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let output_dir = dir.path().join("out");
+    let video_dir = output_dir.join("BV1fk4y1x7Aa");
+    std::fs::create_dir_all(&video_dir).unwrap();
+    let archive = Archive::load(&dir.path().join("archive.json")).unwrap();
+    // 上次下载时两个分P的顺序相反，标题也不同
+    for (cid, page) in [(1001, 2), (1002, 1)] {
+        archived_file(&archive, &video_dir, "BV1fk4y1x7Aa", cid, page).await;
+    }
+
+    let report = Subscription {
+        retag: true,
+        ..subscription("BV1fk4y1x7Aa")
+    }
+    .download(&user, &output_dir, Some(&archive))
+    .await
+    .unwrap();
+    let statuses: Vec<_> = report.tracks.iter().map(|track| track.status).collect();
+    assert_eq!(
+        statuses,
+        [
+            TrackStatus::Retagged,
+            TrackStatus::Retagged,
+            TrackStatus::Unavailable
+        ]
+    );
+
+    for (cid, page, part) in [(1001, 1, "第一首"), (1002, 2, "第二首")] {
+        let entry = archive.get(cid, None).unwrap();
+        let path = video_dir.join(format!("测试合集-P{}.mp3", page));
+        assert_eq!((entry.page, entry.part.as_str()), (page, part));
+        assert_eq!(entry.path, path);
+        let tagged = lofty::read_from_path(&path).unwrap();
+        let tag = tagged.primary_tag().unwrap();
+        assert_eq!(tag.title().as_deref(), Some(part));
+        assert_eq!(tag.track(), Some(page as u32));
+    }
+    assert_eq!(std::fs::read_dir(&video_dir).unwrap().count(), 2);
+}
+
+#[tokio::test]
+async fn failed_renumber_restores_original_file() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let output_dir = dir.path().join("out");
+    let video_dir = output_dir.join("BV1fk4y1x7Aa");
+    std::fs::create_dir_all(&video_dir).unwrap();
+    let archive = Archive::load(&dir.path().join("archive.json")).unwrap();
+    let original = archived_file(&archive, &video_dir, "BV1fk4y1x7Aa", 1001, 2).await;
+    // 新位置被非空目录占用，第二步重命名失败
+    let blocker = video_dir.join("测试合集-P1.mp3");
+    std::fs::create_dir_all(&blocker).unwrap();
+    std::fs::write(blocker.join("keep"), "").unwrap();
+
+    let report = subscription("BV1fk4y1x7Aa")
+        .download(&user, &output_dir, Some(&archive))
+        .await
+        .unwrap();
+
+    assert_eq!(report.tracks[0].status, TrackStatus::Failed);
+    assert!(original.exists());
+    assert_eq!(archive.get(1001, None).unwrap().path, original);
+    let leftovers: Vec<_> = std::fs::read_dir(&video_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|name| name.to_string_lossy().ends_with(".renumber"))
+        .collect();
+    assert!(leftovers.is_empty());
+}
+
+#[tokio::test]
+async fn filters_exclude_videos_and_parts() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let output_dir = dir.path().join("out");
+
+    let plan = Subscription {
+        filter: ContentFilter {
+            part_exclude: Some("第二".to_string()),
+            tids: vec![130],
+            ..Default::default()
+        },
+        ..subscription("BV1fk4y1x7Aa")
+    }
+    .plan(&user, &output_dir, None)
+    .await
+    .unwrap();
+    let actions: Vec<_> = plan.tracks.iter().map(|track| track.action).collect();
+    assert_eq!(
+        actions,
+        [
+            PlanAction::Download,
+            PlanAction::Filtered,
+            PlanAction::Unavailable
+        ]
+    );
+
+    // 视频级条件不满足时，全部分P在请求音频流之前被排除，已下载的文件保持不变
+    let archive = Archive::load(&dir.path().join("archive.json")).unwrap();
+    let gone = archived_file(&archive, dir.path(), "BV1fk4y1x7Aa", 9999, 4).await;
+    let renumbered = archived_file(&archive, dir.path(), "BV1fk4y1x7Aa", 1001, 2).await;
+    let report = Subscription {
+        filter: ContentFilter {
+            min_views: Some(100000),
+            ..Default::default()
+        },
+        on_removed: RemovedPolicy::Delete,
+        ..subscription("BV1fk4y1x7Aa")
+    }
+    .download(&user, &output_dir, Some(&archive))
+    .await
+    .unwrap();
+    assert_eq!(report.count(TrackStatus::Filtered), 3);
+    assert!(report.removed.is_empty());
+    assert!(gone.exists() && renumbered.exists());
+    assert_eq!(archive.get(1001, None).unwrap().path, renumbered);
+    assert!(!output_dir.exists());
+
+    let result = Subscription {
+        filter: ContentFilter {
+            title_include: Some("[".to_string()),
+            ..Default::default()
+        },
+        ..subscription("BV1fk4y1x7Aa")
+    }
+    .download(&user, &output_dir, None)
+    .await;
+    assert!(matches!(result, Err(BilidownError::ArgumentError(_))));
+}
+
+#[tokio::test]
+async fn dry_run_plans_without_downloading() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let output_dir = dir.path().join("out");
+    let archive = Archive::load(&dir.path().join("archive.json")).unwrap();
+    let archived = archived_file(&archive, dir.path(), "BV1fk4y1x7Aa", 1001, 1).await;
+    let video_dir = output_dir.join("BV1fk4y1x7Aa");
+    std::fs::create_dir_all(&video_dir).unwrap();
+    std::fs::write(video_dir.join("测试合集-P2.mp3"), "old").unwrap();
+
+    let plan = subscription("BV1fk4y1x7Aa")
+        .plan(&user, &output_dir, Some(&archive))
+        .await
+        .unwrap();
+
+    let actions: Vec<_> = plan.tracks.iter().map(|track| track.action).collect();
+    assert_eq!(
+        actions,
+        [
+            PlanAction::Skip,
+            PlanAction::Overwrite,
+            PlanAction::Unavailable
+        ]
+    );
+    assert_eq!(plan.tracks[0].path.as_ref(), Some(&archived));
+    let tags = plan.tracks[1].tags.as_ref().unwrap();
+    assert_eq!(tags.title, "第二首");
+    assert_eq!(tags.artist, "测试UP主");
+    assert_eq!(tags.album, "测试合集");
+    // 预演不应写入任何文件
+    assert_eq!(std::fs::read_dir(&video_dir).unwrap().count(), 1);
+    assert_eq!(archive.entries().len(), 1);
+}
+
+#[tokio::test]
+async fn dry_run_uses_configured_format_quality_and_filename() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let output_dir = dir.path().join("out");
+
+    let mut subscription = Subscription {
+        format: Some(AudioFormat::Flac),
+        filename: Some("{page} - {part_title}".to_string()),
+        ..subscription("BV1fk4y1x7Aa")
+    };
+    Defaults {
+        quality: Some(AudioQuality::Q132K),
+        filename: Some("{title}".to_string()),
+        ..Default::default()
+    }
+    .apply(&mut subscription);
+    let plan = subscription.plan(&user, &output_dir, None).await.unwrap();
+
+    let track = &plan.tracks[0];
+    assert_eq!(track.action, PlanAction::Download);
+    assert_eq!(track.format, Some(AudioFormat::Flac));
+    assert!(track.quality.as_deref().unwrap().starts_with("132K"));
+    assert_eq!(
+        track.path.as_deref(),
+        Some(
+            output_dir
+                .join("BV1fk4y1x7Aa")
+                .join("1 - 第一首.flac")
+                .as_path()
+        )
+    );
+
+    // 多P视频的模板中没有 {page} 时追加分P编号，各分P不会写入同一文件
+    let plan = Subscription {
+        format: Some(AudioFormat::Mp3),
+        filename: Some("{artist}/{title}".to_string()),
+        ..subscription
+    }
+    .plan(&user, &output_dir, None)
+    .await
+    .unwrap();
+    let paths: Vec<_> = plan
+        .tracks
+        .iter()
+        .filter_map(|track| track.path.clone())
+        .collect();
+    let video_dir = output_dir.join("BV1fk4y1x7Aa").join("测试UP主");
+    assert_eq!(
+        paths,
+        [
+            video_dir.join("测试合集-P1.mp3"),
+            video_dir.join("测试合集-P2.mp3")
+        ]
+    );
+}
+
+#[tokio::test]
+async fn output_dir_overrides_are_planned_and_archived_separately() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let output_dir = dir.path().join("out");
+    let archive = Archive::load(&dir.path().join("archive.json")).unwrap();
+    archived_file(&archive, dir.path(), "BV1fk4y1x7Aa", 1001, 1).await;
+
+    let plan = subscription("BV1fk4y1x7Aa")
+        .plan(&user, &output_dir, Some(&archive))
+        .await
+        .unwrap();
+    assert_eq!(plan.tracks[0].action, PlanAction::Skip);
+
+    // 同一视频的另一份订阅写入单独的目录，不受默认目录下载记录的影响
+    let phone = Subscription {
+        output_dir: Some(PathBuf::from("phone")),
+        filename: Some("{artist}/{title}/{page} {part_title}".to_string()),
+        format: Some(AudioFormat::Mp3),
+        bitrate: Some(128),
+        ..subscription("BV1fk4y1x7Aa")
+    };
+    assert_eq!(phone.key(), "BV1fk4y1x7Aa@phone");
+    let plan = phone
+        .plan(&user, &output_dir, Some(&archive))
+        .await
+        .unwrap();
+    let track = &plan.tracks[0];
+    assert_eq!(track.action, PlanAction::Download);
+    assert_eq!(
+        track.path.as_deref(),
+        Some(
+            output_dir
+                .join("phone")
+                .join("测试UP主")
+                .join("测试合集")
+                .join("1 第一首.mp3")
+                .as_path()
+        )
+    );
+    assert!(archive.get(1001, phone.archive_scope()).is_none());
+    assert!(
+        archive
+            .entries_for("BV1fk4y1x7Aa", phone.archive_scope())
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn lyrics_prefer_chinese_subtitles() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+
+    let lyrics = lyrics::fetch(&user, "BV1fk4y1x7Aa", 1001).await.unwrap();
+    assert_eq!(
+        lyrics.as_deref(),
+        Some("[00:00.50]第一句歌词\n[00:02.30]第二句歌词\n")
+    );
+    assert_eq!(
+        lyrics::fetch(&user, "BV1fk4y1x7Aa", 1002).await.unwrap(),
+        None
+    );
+}
+
+#[tokio::test]
+async fn cover_lyrics_and_extra_tags_are_written() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let video = ov_bilidown::VideoBasicInfo::new_from_bvid(&user, "BV1fk4y1x7Aa")
+        .await
+        .unwrap();
+    let part = &video.pages.as_ref().unwrap()[0];
+    let path = dir.path().join("track.mp3");
+    std::fs::write(&path, common::sample_mp3(20)).unwrap();
+
+    let subscription = Subscription {
+        extra_tags: [
+            ("genre", "VOCALOID"),
+            ("album_artist", "{artist}"),
+            ("mood", "忽略"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect(),
+        ..subscription("BV1fk4y1x7Aa")
+    };
+    converter::add_metadata_to_file(&path, &video, part, &subscription).unwrap();
+    let cover = video.fetch_cover(&user).await.unwrap();
+    converter::embed_cover_and_lyrics(&path, Some(&cover), Some("[00:00.50]第一句歌词\n")).unwrap();
+
+    let tagged = lofty::read_from_path(&path).unwrap();
+    let tag = tagged.primary_tag().unwrap();
+    assert_eq!(tag.genre().as_deref(), Some("VOCALOID"));
+    assert_eq!(tag.get_string(&ItemKey::AlbumArtist), Some("测试UP主"));
+    assert_eq!(
+        tag.get_string(&ItemKey::Lyrics),
+        Some("[00:00.50]第一句歌词\n")
+    );
+    let picture = &tag.pictures()[0];
+    assert_eq!(picture.pic_type(), PictureType::CoverFront);
+    assert_eq!(picture.data(), common::sample_png().as_slice());
+}
+
+#[tokio::test]
+async fn download_reports_progress_events() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let mut events = user.progress().channel();
+    let video = ov_bilidown::VideoBasicInfo::new_from_bvid(&user, "BV1fk4y1x7Aa")
+        .await
+        .unwrap();
+
+    video
+        .download_best_quality_audios_to_file(
+            &user,
+            dir.path(),
+            &subscription("BV1fk4y1x7Aa"),
+            None,
+        )
+        .await
+        .unwrap();
+
+    let mut resolved = None;
+    let mut selected = Vec::new();
+    let mut finished = Vec::new();
+    let mut last_progress = None;
+    while let Ok(event) = events.try_recv() {
+        match event {
+            ProgressEvent::VideoResolved { title, parts, .. } => resolved = Some((title, parts)),
+            ProgressEvent::StreamSelected { page, quality, .. } => selected.push((page, quality)),
+            ProgressEvent::TrackFinished { track, .. } => finished.push(track),
+            ProgressEvent::DownloadProgress {
+                page: 1,
+                downloaded,
+                total,
+                ..
+            } => last_progress = Some((downloaded, total)),
+            _ => {}
+        }
+    }
+    assert_eq!(resolved, Some(("测试合集".to_string(), 3)));
+    selected.sort();
+    assert_eq!(
+        selected,
+        [
+            (1, "192K (192kbps)".to_string()),
+            (2, "132K (132kbps)".to_string())
+        ]
+    );
+    finished.sort_by_key(|track| track.page);
+    assert_eq!(finished.len(), 3);
+    assert_eq!(finished[2].status, TrackStatus::Unavailable);
+    // 静音 MP3 共 200 帧，每帧 417 字节
+    let size = common::sample_mp3(200).len() as u64;
+    assert_eq!(last_progress, Some((size, Some(size))));
+}
+
+#[tokio::test]
+async fn partial_download_resumes_from_existing_bytes() {
+    let server = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let user = logged_in_user(&server, dir.path()).await;
+    let url = format!("http://{}/upgcxcode/audio.m4s", server.addr);
+    let sample = common::sample_mp3(200);
+    std::fs::write(dir.path().join("audio.part"), &sample[..1000]).unwrap();
+
+    let progress = std::sync::Mutex::new(Vec::new());
+    user.download_to_file(&url, dir.path(), "audio.part", |downloaded, total| {
+        progress.lock().unwrap().push((downloaded, total))
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        std::fs::read(dir.path().join("audio.part")).unwrap(),
+        sample
+    );
+    let progress = progress.into_inner().unwrap();
+    assert_eq!(progress[0], (1000, Some(sample.len() as u64)));
+
+    // 已下载完整的文件不再重复下载
+    user.download_to_file(&url, dir.path(), "audio.part", |_, _| {})
+        .await
+        .unwrap();
+    assert_eq!(
+        std::fs::read(dir.path().join("audio.part")).unwrap(),
+        sample
+    );
+}
@@ -0,0 +1,201 @@
+//! Control API tests against a local fake Bilibili server
+
+mod common;
+
+use std::{path::Path, time::Duration};
+
+use common::FakeBilibili;
+use ov_bilidown::{
+    archive::Archive,
+    config::{Defaults, Overrides},
+    limit::LimitConfig,
+    queue::JobQueue,
+    server::{Server, ServerConfig},
+    user::User,
+};
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+
+/// 启动控制接口，返回其基础地址
+async fn start_server(bilibili: &FakeBilibili, dir: &Path, config: ServerConfig) -> String {
+    let mut user = User::new_anonymous(bilibili.endpoints(), None);
+    user.set_limits(&LimitConfig {
+        api_rate: 0.0,
+        ..Default::default()
+    });
+    let server = Server::new(
+        user,
+        Archive::load(&dir.join("archive.json")).unwrap(),
+        JobQueue::load(&dir.join("queue.json")).unwrap(),
+        dir.join("out"),
+        dir.join("sub.toml"),
+        Defaults::default(),
+        Overrides::default(),
+        &config,
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let router = server.router();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+/// 轮询任务直到结束
+async fn wait_for_job(client: &reqwest::Client, base: &str, id: u64) -> Value {
+    for _ in 0..100 {
+        let job: Value = client
+            .get(format!("{}/jobs/{}", base, id))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        if !matches!(job["status"].as_str(), Some("queued" | "running")) {
+            return job;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("任务 {} 未在预期时间内结束", id);
+}
+
+#[tokio::test]
+async fn enqueue_runs_jobs_and_records_history() {
+    let bilibili = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let base = start_server(&bilibili, dir.path(), ServerConfig::default()).await;
+    let client = reqwest::Client::new();
+
+    let resp = client
+        .post(format!("{}/jobs", base))
+        .json(&json!({ "url": "https://www.bilibili.com/video/BV1nA4y1x7Ab/?p=1" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::ACCEPTED);
+    let job: Value = resp.json().await.unwrap();
+    assert_eq!(job["subscription"]["bvid"], "BV1nA4y1x7Ab");
+
+    let job = wait_for_job(&client, &base, job["id"].as_u64().unwrap()).await;
+    assert_eq!(job["status"], "completed");
+    assert_eq!(job["report"]["bvid"], "BV1nA4y1x7Ab");
+    // 进度事件按任务编号记录到该任务
+    assert!(job["parts"].as_u64().is_some());
+    assert!(!job["tracks"].as_object().unwrap().is_empty());
+
+    let deleted: Value = client
+        .post(format!("{}/jobs", base))
+        .json(&json!({ "url": "BV1xx411c7Xd" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let deleted = wait_for_job(&client, &base, deleted["id"].as_u64().unwrap()).await;
+    assert_eq!(deleted["status"], "failed");
+    assert!(deleted["error"].as_str().unwrap().contains("啥都木有"));
+
+    let history: Value = client
+        .get(format!("{}/history", base))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(history.as_array().unwrap().len(), 2);
+
+    // 已结束的任务不能取消
+    let resp = client
+        .delete(format!("{}/jobs/{}", base, deleted["id"]))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn rejects_invalid_input_and_missing_token() {
+    let bilibili = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let config = ServerConfig {
+        token: Some("secret".to_string()),
+        ..Default::default()
+    };
+    let base = start_server(&bilibili, dir.path(), config).await;
+    let client = reqwest::Client::new();
+
+    let resp = client.get(format!("{}/queue", base)).send().await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let resp = client
+        .get(format!("{}/queue", base))
+        .bearer_auth("secreT")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let resp = client
+        .post(format!("{}/jobs", base))
+        .bearer_auth("secret")
+        .json(&json!({ "url": "https://example.com/not-a-video" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let resp = client
+        .get(format!("{}/jobs/42", base))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn history_keeps_latest_finished_jobs() {
+    let bilibili = FakeBilibili::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let config = ServerConfig {
+        history: 1,
+        ..Default::default()
+    };
+    let base = start_server(&bilibili, dir.path(), config).await;
+    let client = reqwest::Client::new();
+
+    let mut ids = Vec::new();
+    for url in ["BV1nA4y1x7Ab", "BV1xx411c7Xd"] {
+        let job: Value = client
+            .post(format!("{}/jobs", base))
+            .json(&json!({ "url": url }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let id = job["id"].as_u64().unwrap();
+        wait_for_job(&client, &base, id).await;
+        ids.push(id);
+    }
+
+    let history: Value = client
+        .get(format!("{}/history", base))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let history = history.as_array().unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0]["id"].as_u64(), Some(ids[1]));
+    let resp = client
+        .get(format!("{}/jobs/{}", base, ids[0]))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+}